@@ -0,0 +1,162 @@
+use crate::LinkedOutcome;
+
+use cargo::core::PackageId;
+use maplit::{btreemap, btreeset};
+
+use std::collections::BTreeSet;
+
+/// Renders the resolved graph of each platform in the DOT language.
+///
+/// Used edges are solid and the others are dashed. With `collapse`, only the members and their
+/// direct dependencies are drawn, each labeled with the number of the packages hidden behind it.
+pub(crate) fn graph(outcome: &LinkedOutcome, collapse: bool) -> String {
+    let mut ret = "".to_owned();
+    for (triple, platform) in &outcome.platforms {
+        let linked = &platform.union;
+        let members = platform.members.keys().cloned().collect::<BTreeSet<_>>();
+        let edges = |from: PackageId| {
+            linked
+                .resolved_edges
+                .get(&from)
+                .into_iter()
+                .flatten()
+                .map(move |(&to, &used)| (from, to, used))
+        };
+
+        let mut nodes = &(&linked.used | &linked.unused.trivial) | &linked.unused.maybe_obsolete;
+        nodes.extend(&members);
+        let mut hidden = btreemap!();
+        if collapse {
+            let mut direct = members.clone();
+            direct.extend(members.iter().flat_map(|&m| edges(m)).map(|(_, to, _)| to));
+            for &id in direct.difference(&members) {
+                let mut reached = btreeset!(id);
+                let mut stack = vec![id];
+                while let Some(from) = stack.pop() {
+                    for (_, to, _) in edges(from) {
+                        if !direct.contains(&to) && reached.insert(to) {
+                            stack.push(to);
+                        }
+                    }
+                }
+                hidden.insert(id, reached.len() - 1);
+            }
+            nodes = direct;
+        }
+
+        ret += &format!("digraph {} {{\n", quote(triple));
+        ret += "    node [style=filled];\n";
+        for &id in &nodes {
+            let fillcolor = if linked.used.contains(&id) {
+                "palegreen"
+            } else if linked.unused.trivial.contains(&id) {
+                "lightgrey"
+            } else if linked.unused.maybe_obsolete.contains(&id) {
+                "lightpink"
+            } else {
+                "white"
+            };
+            let shape = if members.contains(&id) {
+                "box"
+            } else {
+                "ellipse"
+            };
+            let mut label = format!("{}\n{}", id.name(), id.version());
+            if let Some(n) = hidden.get(&id).filter(|&&n| n > 0) {
+                label += &format!("\n(+{})", n);
+            }
+            ret += &format!(
+                "    {} [label={}, shape={}, fillcolor={}];\n",
+                quote(&id.to_string()),
+                quote(&label),
+                shape,
+                fillcolor,
+            );
+        }
+        for &from in &nodes {
+            for (from, to, used) in edges(from).filter(|(_, to, _)| nodes.contains(to)) {
+                if collapse && !members.contains(&from) {
+                    continue;
+                }
+                ret += &format!(
+                    "    {} -> {} [style={}];\n",
+                    quote(&from.to_string()),
+                    quote(&to.to_string()),
+                    if used { "solid" } else { "dashed" },
+                );
+            }
+        }
+        ret += "}\n";
+    }
+    ret
+}
+
+fn quote(s: &str) -> String {
+    let s = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", s)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{id, outcome_for};
+    use crate::{LinkedOutcome, LinkedPackages, LinkedPackagesUnused};
+
+    use maplit::{btreemap, btreeset};
+
+    /// `root` uses `a` but not `b`. `a` uses `c`, and `b` would use `d`.
+    fn outcome() -> LinkedOutcome {
+        let linked = LinkedPackages {
+            used: btreeset!(id("a"), id("c")),
+            unused: LinkedPackagesUnused {
+                trivial: btreeset!(id("d")),
+                maybe_obsolete: btreeset!(id("b")),
+            },
+            resolved_edges: btreemap!(
+                id("root") => btreemap!(id("a") => true, id("b") => false),
+                id("a") => btreemap!(id("c") => true),
+                id("b") => btreemap!(id("d") => false),
+            ),
+            ..Default::default()
+        };
+        outcome_for(btreemap!(id("root") => linked))
+    }
+
+    #[test]
+    fn test_graph() {
+        assert_eq!(
+            super::graph(&outcome(), false),
+            r#"digraph "x86_64-unknown-linux-gnu" {
+    node [style=filled];
+    "a v0.0.0 (/a)" [label="a\n0.0.0", shape=ellipse, fillcolor=palegreen];
+    "b v0.0.0 (/b)" [label="b\n0.0.0", shape=ellipse, fillcolor=lightpink];
+    "c v0.0.0 (/c)" [label="c\n0.0.0", shape=ellipse, fillcolor=palegreen];
+    "d v0.0.0 (/d)" [label="d\n0.0.0", shape=ellipse, fillcolor=lightgrey];
+    "root v0.0.0 (/root)" [label="root\n0.0.0", shape=box, fillcolor=white];
+    "a v0.0.0 (/a)" -> "c v0.0.0 (/c)" [style=solid];
+    "b v0.0.0 (/b)" -> "d v0.0.0 (/d)" [style=dashed];
+    "root v0.0.0 (/root)" -> "a v0.0.0 (/a)" [style=solid];
+    "root v0.0.0 (/root)" -> "b v0.0.0 (/b)" [style=dashed];
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn test_graph_collapse() {
+        assert_eq!(
+            super::graph(&outcome(), true),
+            r#"digraph "x86_64-unknown-linux-gnu" {
+    node [style=filled];
+    "a v0.0.0 (/a)" [label="a\n0.0.0\n(+1)", shape=ellipse, fillcolor=palegreen];
+    "b v0.0.0 (/b)" [label="b\n0.0.0\n(+1)", shape=ellipse, fillcolor=lightpink];
+    "root v0.0.0 (/root)" [label="root\n0.0.0", shape=box, fillcolor=white];
+    "root v0.0.0 (/root)" -> "a v0.0.0 (/a)" [style=solid];
+    "root v0.0.0 (/root)" -> "b v0.0.0 (/b)" [style=dashed];
+}
+"#,
+        );
+    }
+}
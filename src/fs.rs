@@ -1,7 +1,9 @@
 use cargo::util::FileLock;
 use failure::{format_err, Fallible, ResultExt as _};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 
+use std::fs::DirEntry;
 use std::io::{Read as _, Seek as _, SeekFrom, Write as _};
 use std::marker::PhantomData;
 use std::path::Path;
@@ -13,6 +15,19 @@ pub(crate) fn write(path: impl AsRef<Path>, contents: impl AsRef<str>) -> Fallib
         .map_err(Into::into)
 }
 
+pub(crate) fn read(path: &Path) -> Fallible<Vec<u8>> {
+    std::fs::read(path)
+        .with_context(|_| format_err!("Failed to read {}", path.display()))
+        .map_err(Into::into)
+}
+
+pub(crate) fn read_dir(path: &Path) -> Fallible<Vec<DirEntry>> {
+    std::fs::read_dir(path)
+        .and_then(|entries| entries.collect())
+        .with_context(|_| format_err!("Failed to read {}", path.display()))
+        .map_err(Into::into)
+}
+
 pub(crate) fn read_src(path: &Path) -> Fallible<syn::File> {
     let src = std::fs::read_to_string(path)
         .with_context(|_| failure::err_msg(format!("Failed to read {}", path.display())))?;
@@ -21,12 +36,12 @@ pub(crate) fn read_src(path: &Path) -> Fallible<syn::File> {
         .map_err(Into::into)
 }
 
-pub(crate) struct JsonFileLock<T: Default + miniserde::Serialize + DeserializeOwned> {
+pub(crate) struct JsonFileLock<T: Default + Serialize + DeserializeOwned> {
     lock: FileLock,
     phantom: PhantomData<fn() -> T>,
 }
 
-impl<T: Default + miniserde::Serialize + DeserializeOwned> JsonFileLock<T> {
+impl<T: Default + Serialize + DeserializeOwned> JsonFileLock<T> {
     pub(crate) fn read(&mut self) -> Fallible<T> {
         let mut value = "".to_owned();
         self.lock
@@ -47,7 +62,7 @@ impl<T: Default + miniserde::Serialize + DeserializeOwned> JsonFileLock<T> {
     }
 
     pub(crate) fn write(&mut self, value: &T) -> Fallible<()> {
-        let value = miniserde::json::to_string(&value);
+        let value = serde_json::to_string(value)?;
         self.lock
             .seek(SeekFrom::Start(0))
             .and_then(|_| self.lock.file().set_len(0))
@@ -59,7 +74,7 @@ impl<T: Default + miniserde::Serialize + DeserializeOwned> JsonFileLock<T> {
     }
 }
 
-impl<T: Default + miniserde::Serialize + DeserializeOwned> From<FileLock> for JsonFileLock<T> {
+impl<T: Default + Serialize + DeserializeOwned> From<FileLock> for JsonFileLock<T> {
     fn from(lock: FileLock) -> Self {
         Self {
             lock,
@@ -0,0 +1,283 @@
+use crate::{LinkedDirectDependency, LinkedEdge, LinkedOutcome, LinkedPackages, UsedAs};
+
+use ansi_term::{Colour, Style};
+use cargo::core::PackageId;
+use maplit::btreemap;
+
+use std::collections::BTreeMap;
+
+/// Renders the used packages of each member as a tree from the root target, followed by the
+/// unused ones with the reasons.
+pub(crate) fn tree(outcome: &LinkedOutcome, color: bool) -> String {
+    let paint = |style, s: &str| paint(color, style, s);
+    let mut ret = "".to_owned();
+    for (triple, platform) in &outcome.platforms {
+        for (&member, linked) in &platform.members {
+            if !ret.is_empty() {
+                ret += "\n";
+            }
+            ret += &format!(
+                "{} {}\n",
+                paint(Style::new().bold(), &package(member)),
+                paint(Style::new().dimmed(), &format!("({})", triple)),
+            );
+
+            let mut children = BTreeMap::<_, Vec<_>>::new();
+            for (&to, edge) in &linked.reached_via {
+                let from = Some(edge.from).filter(|_| !edge.from_root);
+                children.entry(from).or_default().push((to, edge));
+            }
+            let push_children = |stack: &mut Vec<_>, from, indent: &str| {
+                let nodes = children.get(&from).map(|c| &**c).unwrap_or_default();
+                let n = nodes.len();
+                for (i, &(to, edge)) in nodes.iter().enumerate().rev() {
+                    stack.push((to, edge, indent.to_owned(), i + 1 == n));
+                }
+            };
+            let mut stack = vec![];
+            push_children(&mut stack, None, "");
+            while let Some((to, edge, indent, is_last)) = stack.pop() {
+                ret += &indent;
+                ret += if is_last { "└── " } else { "├── " };
+                ret += &node(to, edge, linked.used_as.get(&to), color);
+                ret += "\n";
+                let indent = indent + if is_last { "    " } else { "│   " };
+                push_children(&mut stack, Some(to), &indent);
+            }
+
+            let unused = unused_reasons(linked);
+            if !unused.is_empty() {
+                ret += &format!("{}\n", paint(Colour::Red.bold(), "unused:"));
+                for (id, reason) in unused {
+                    ret += &format!(
+                        "    {} {}: {}\n",
+                        paint(Colour::Red.normal(), "✗"),
+                        package(id),
+                        reason,
+                    );
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// Renders the packages of each platform as a table of the name, the version, the source and
+/// the status.
+pub(crate) fn table(outcome: &LinkedOutcome, color: bool) -> String {
+    let paint = |style, s: &str| paint(color, style, s);
+    let mut ret = "".to_owned();
+    for (triple, platform) in &outcome.platforms {
+        let linked = &platform.union;
+        let mut rows = btreemap!();
+        for &id in &linked.used {
+            let status = match linked.used_as.get(&id) {
+                None | Some(UsedAs::Runtime) => "used (runtime)",
+                Some(UsedAs::ProcMacro) => "used (proc-macro)",
+                Some(UsedAs::Build) => "used (build)",
+            };
+            rows.insert(id, (status, Colour::Green));
+        }
+        for &id in &linked.unused.trivial {
+            rows.insert(id, ("unused (not built)", Colour::Red));
+        }
+        for &id in &linked.unused.maybe_obsolete {
+            rows.insert(id, ("unused", Colour::Red));
+        }
+
+        let header = ["NAME", "VERSION", "SOURCE", "STATUS"];
+        let rows = rows
+            .into_iter()
+            .map(|(id, (status, colour))| {
+                let row = [
+                    id.name().to_string(),
+                    id.version().to_string(),
+                    source(id),
+                    status.to_owned(),
+                ];
+                (row, colour)
+            })
+            .collect::<Vec<_>>();
+        let mut widths = header.iter().map(|h| h.len()).collect::<Vec<_>>();
+        for (row, _) in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        if !ret.is_empty() {
+            ret += "\n";
+        }
+        if outcome.platforms.len() > 1 {
+            ret += &format!("{}\n", triple);
+        }
+        let header = header
+            .iter()
+            .zip(&widths)
+            .map(|(h, &w)| format!("{:w$}", h, w = w))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let header = header.trim_end();
+        ret += &paint(Style::new().bold(), header);
+        ret += "\n";
+        for (row, colour) in rows {
+            let (status, cells) = row.split_last().unwrap();
+            for (cell, &w) in cells.iter().zip(&widths) {
+                ret += &format!("{:w$}  ", cell, w = w);
+            }
+            ret += &paint(colour.normal(), status);
+            ret += "\n";
+        }
+    }
+    ret
+}
+
+fn node(id: PackageId, edge: &LinkedEdge, used_as: Option<&UsedAs>, color: bool) -> String {
+    let mut ret = package(id);
+    if edge.extern_crate_name != id.name().replace('-', "_") {
+        ret += &format!(" as `{}`", edge.extern_crate_name);
+    }
+    let tag = match used_as {
+        None | Some(UsedAs::Runtime) => None,
+        Some(UsedAs::ProcMacro) => Some("(proc-macro)"),
+        Some(UsedAs::Build) => Some("(build)"),
+    };
+    if let Some(tag) = tag {
+        ret += " ";
+        ret += &paint(color, Colour::Yellow.normal(), tag);
+    }
+    ret
+}
+
+/// Each unused package with the reason. Declared dependencies are explained by their entries.
+fn unused_reasons(linked: &LinkedPackages) -> Vec<(PackageId, String)> {
+    let declared = |id: PackageId| {
+        linked
+            .direct
+            .iter()
+            .filter(|d| d.package == id && !d.used)
+            .collect::<Vec<_>>()
+    };
+    let reason = |id: PackageId, otherwise: &str| match &*declared(id) {
+        [] => otherwise.to_owned(),
+        entries => entries
+            .iter()
+            .map(|&d| declared_reason(d))
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+
+    let mut ret = linked
+        .unused
+        .trivial
+        .iter()
+        .map(|&id| (id, reason(id, "not built for this target")))
+        .chain(linked.unused.maybe_obsolete.iter().map(|&id| {
+            let otherwise = "built, but no used crate refers to it";
+            (id, reason(id, otherwise))
+        }))
+        .collect::<Vec<_>>();
+    ret.sort_by_key(|&(id, _)| id);
+    ret
+}
+
+fn declared_reason(entry: &LinkedDirectDependency) -> String {
+    let mut ret = format!("`{}` is declared", entry.name_in_toml);
+    if let (Some(line), Some(column)) = (entry.line, entry.column) {
+        ret += &format!(" at {}:{}:{}", entry.manifest_path.display(), line, column,);
+    }
+    ret += if entry.active {
+        " but never used"
+    } else {
+        " for another platform"
+    };
+    ret
+}
+
+fn paint(color: bool, style: Style, s: &str) -> String {
+    if color {
+        style.paint(s).to_string()
+    } else {
+        s.to_owned()
+    }
+}
+
+fn package(id: PackageId) -> String {
+    format!("{} v{}", id.name(), id.version())
+}
+
+fn source(id: PackageId) -> String {
+    if id.source_id().is_default_registry() {
+        "crates.io".to_owned()
+    } else {
+        id.source_id().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{id, outcome_for};
+    use crate::{LinkedEdge, LinkedOutcome, LinkedPackages, LinkedPackagesUnused, UsedAs};
+
+    use maplit::{btreemap, btreeset};
+
+    fn edge(from: &str, target: &str, extern_crate_name: &str, from_root: bool) -> LinkedEdge {
+        LinkedEdge {
+            from: id(from),
+            target: target.to_owned(),
+            extern_crate_name: extern_crate_name.to_owned(),
+            from_root,
+        }
+    }
+
+    fn outcome() -> LinkedOutcome {
+        let linked = LinkedPackages {
+            used: btreeset!(id("a"), id("b"), id("c")),
+            used_as: btreemap!(
+                id("a") => UsedAs::Runtime,
+                id("b") => UsedAs::Runtime,
+                id("c") => UsedAs::Build,
+            ),
+            unused: LinkedPackagesUnused {
+                trivial: btreeset!(id("d")),
+                maybe_obsolete: btreeset!(id("e")),
+            },
+            reached_via: btreemap!(
+                id("a") => edge("root", "bin \"root\"", "a", true),
+                id("b") => edge("a", "lib", "b_renamed", false),
+                id("c") => edge("a", "custom-build", "c", false),
+            ),
+            ..Default::default()
+        };
+        outcome_for(btreemap!(id("root") => linked))
+    }
+
+    #[test]
+    fn test_tree() {
+        assert_eq!(
+            super::tree(&outcome(), false),
+            r#"root v0.0.0 (x86_64-unknown-linux-gnu)
+└── a v0.0.0
+    ├── b v0.0.0 as `b_renamed`
+    └── c v0.0.0 (build)
+unused:
+    ✗ d v0.0.0: not built for this target
+    ✗ e v0.0.0: built, but no used crate refers to it
+"#,
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        assert_eq!(
+            super::table(&outcome(), false),
+            r#"NAME  VERSION  SOURCE  STATUS
+a     0.0.0    /a      used (runtime)
+b     0.0.0    /b      used (runtime)
+c     0.0.0    /c      used (build)
+d     0.0.0    /d      unused (not built)
+e     0.0.0    /e      unused
+"#,
+        );
+    }
+}
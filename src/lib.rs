@@ -1,12 +1,16 @@
 //! List actually used crates.
 //!
 //! ```no_run
-//! use cargo_linked::{CargoLinked, LinkedPackages};
+//! use cargo_linked::{CargoLinked, LinkedOutcome};
 //!
 //! let mut config = cargo::Config::default()?;
 //!
-//! let LinkedPackages { used, unused } = CargoLinked {
+//! let LinkedOutcome { platforms, .. } = CargoLinked {
 //!     demonstrate: todo!(),
+//!     workspace: todo!(),
+//!     package: todo!(),
+//!     exclude: todo!(),
+//!     all_targets: todo!(),
 //!     lib: todo!(),
 //!     debug: todo!(),
 //!     all_features: todo!(),
@@ -14,14 +18,25 @@
 //!     frozen: todo!(),
 //!     locked: todo!(),
 //!     offline: todo!(),
+//!     verbose: todo!(),
 //!     jobs: todo!(),
 //!     bin: todo!(),
 //!     example: todo!(),
 //!     test: todo!(),
 //!     bench: todo!(),
+//!     target: todo!(),
 //!     features: todo!(),
 //!     manifest_path: todo!(),
 //!     color: todo!(),
+//!     why: todo!(),
+//!     fix: todo!(),
+//!     dry_run: todo!(),
+//!     backend: todo!(),
+//!     format: todo!(),
+//!     collapse: todo!(),
+//!     deny: todo!(),
+//!     policy: todo!(),
+//!     artifact: todo!(),
 //! }
 //! .outcome(&mut config)?;
 //! # cargo::CargoResult::Ok(())
@@ -33,10 +48,19 @@ macro_rules! lazy_regex {
     };
 }
 
+mod dot;
 mod fs;
+mod human;
+mod license;
+mod manifest;
 mod parse;
+pub mod policy;
 mod process;
-mod ser;
+mod sbom;
+pub mod ser;
+mod size;
+#[cfg(test)]
+mod testing;
 mod util;
 
 use crate::fs::JsonFileLock;
@@ -44,14 +68,18 @@ use crate::process::{Rustc, RustcOpts};
 
 use ansi_term::Colour;
 use cargo::core::compiler::{
-    CompileKind, CompileMode, DefaultExecutor, Executor, ProfileKind, Unit,
+    CompileKind, CompileMode, DefaultExecutor, Executor, ProfileKind, TargetInfo, Unit,
 };
 use cargo::core::manifest::{Target, TargetKind};
 use cargo::core::resolver::ResolveOpts;
-use cargo::core::{dependency, Package, PackageId, PackageSet, Resolve, Workspace};
+use cargo::core::shell::{ColorChoice, Verbosity};
+use cargo::core::{
+    dependency, Dependency, Package, PackageId, PackageIdSpec, PackageSet, Resolve, Workspace,
+};
 use cargo::ops::{CleanOptions, CompileOptions, Packages};
 use cargo::util::process_builder::ProcessBuilder;
-use cargo::{CargoResult, CliResult};
+use cargo::{CargoResult, CliError, CliResult};
+use cargo_platform::Cfg;
 use failure::format_err;
 use fixedbitset::FixedBitSet;
 use if_chain::if_chain;
@@ -63,12 +91,14 @@ use structopt::StructOpt;
 use tempdir::TempDir;
 
 use std::borrow::Borrow;
-use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
-use std::io::Write;
+use std::collections::{btree_map, BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{self, IsTerminal as _, Write};
 use std::iter;
 use std::ops::{Deref, Index};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -86,6 +116,30 @@ pub enum Cargo {
 pub struct CargoLinked {
     #[structopt(long, help("Build the target skipping the \"unused\" crates"))]
     pub demonstrate: bool,
+    #[structopt(long, help("Analyze all packages in the workspace"))]
+    pub workspace: bool,
+    #[structopt(
+        short,
+        long,
+        value_name("SPEC"),
+        number_of_values(1),
+        help("Package(s) to analyze")
+    )]
+    pub package: Vec<String>,
+    #[structopt(
+        long,
+        value_name("SPEC"),
+        number_of_values(1),
+        requires("workspace"),
+        help("Exclude packages from the analysis")
+    )]
+    pub exclude: Vec<String>,
+    #[structopt(
+        long,
+        conflicts_with_all(&["lib", "bin", "example", "test", "bench"]),
+        help("Target all of the targets")
+    )]
+    pub all_targets: bool,
     #[structopt(long, help("Target the `lib`"))]
     pub lib: bool,
     #[structopt(long, help("Run in debug mode"))]
@@ -100,6 +154,13 @@ pub struct CargoLinked {
     pub locked: bool,
     #[structopt(long, help("Run without accessing the network"))]
     pub offline: bool,
+    #[structopt(
+        short,
+        long,
+        parse(from_occurrences),
+        help("Use verbose output (-vv very verbose)")
+    )]
+    pub verbose: u32,
     #[structopt(
         short,
         long,
@@ -135,6 +196,13 @@ pub struct CargoLinked {
         help("Target the `bench`")
     )]
     pub bench: Option<String>,
+    #[structopt(
+        long,
+        value_name("TRIPLE"),
+        number_of_values(1),
+        help("Target triple(s) to analyze for")
+    )]
+    pub target: Vec<String>,
     #[structopt(
         long,
         value_name("FEATURES"),
@@ -146,22 +214,235 @@ pub struct CargoLinked {
     pub manifest_path: Option<PathBuf>,
     #[structopt(long, value_name("WHEN"), help("Coloring: auto, always, never"))]
     pub color: Option<String>,
+    #[structopt(
+        long,
+        value_name("SPEC"),
+        help("Print how the package is reached instead of the JSON")
+    )]
+    pub why: Option<String>,
+    #[structopt(
+        long,
+        conflicts_with_all(&["lib", "bin", "example", "test", "bench", "why"]),
+        help("Remove the unused dependencies from the manifests")
+    )]
+    pub fix: bool,
+    #[structopt(
+        long,
+        requires("fix"),
+        help("Print the changes instead of writing them")
+    )]
+    pub dry_run: bool,
+    #[structopt(
+        long,
+        value_name("BACKEND"),
+        default_value("auto"),
+        possible_values(&["auto", "lint", "trial"]),
+        help("How to find the unused externs")
+    )]
+    pub backend: Backend,
+    #[structopt(
+        long,
+        value_name("FORMAT"),
+        default_value("json"),
+        possible_values(&[
+            "json",
+            "human",
+            "table",
+            "dot",
+            "cyclonedx-json",
+            "spdx-json",
+            "licenses",
+            "notices",
+        ]),
+        help("Output format")
+    )]
+    pub format: Format,
+    #[structopt(
+        long,
+        help("Draw only the members and their direct dependencies with `--format dot`")
+    )]
+    pub collapse: bool,
+    #[structopt(
+        long,
+        value_name("LINT"),
+        possible_values(&["unused"]),
+        conflicts_with_all(&["lib", "bin", "example", "test", "bench", "why", "fix"]),
+        help("Exit with 2 if any of the declared dependencies is unused")
+    )]
+    pub deny: Option<Deny>,
+    #[structopt(
+        long,
+        value_name("PATH"),
+        conflicts_with_all(&["lib", "bin", "example", "test", "bench", "why", "fix"]),
+        help("Exit with 2 if any package denied by the policy file is linked")
+    )]
+    pub policy: Option<PathBuf>,
+    #[structopt(
+        long,
+        value_name("PATH"),
+        conflicts_with_all(&["why", "fix", "deny", "policy"]),
+        help("Attribute the symbols in the built ELF file to the used packages as `size_bytes`")
+    )]
+    pub artifact: Option<PathBuf>,
+}
+
+/// How to find the unused `--extern`s of each unit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Backend {
+    /// `Lint`, falling back to `Trial` if the toolchain does not support the lint.
+    Auto,
+    /// Ask rustc once with `--force-warn unused-crate-dependencies`.
+    Lint,
+    /// Remove the externs and compile until rustc stops complaining.
+    Trial,
+}
+
+impl FromStr for Backend {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, failure::Error> {
+        match s {
+            "auto" => Ok(Backend::Auto),
+            "lint" => Ok(Backend::Lint),
+            "trial" => Ok(Backend::Trial),
+            s => Err(format_err!("Unknown backend: {:?}", s)),
+        }
+    }
+}
+
+/// How to print the outcome.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// The whole outcome as JSON.
+    Json,
+    /// A tree from each root target, followed by the unused packages with the reasons.
+    Human,
+    /// A table of the name, the version, the source and the status of each package.
+    Table,
+    /// The resolved graph in the DOT language of Graphviz.
+    Dot,
+    /// A CycloneDX BOM of the packages linked into the artifacts.
+    CycloneDxJson,
+    /// An SPDX document of the packages linked into the artifacts.
+    SpdxJson,
+    /// The linked packages grouped by their licenses.
+    Licenses,
+    /// The license texts of the linked packages.
+    Notices,
+}
+
+impl FromStr for Format {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, failure::Error> {
+        match s {
+            "json" => Ok(Format::Json),
+            "human" => Ok(Format::Human),
+            "table" => Ok(Format::Table),
+            "dot" => Ok(Format::Dot),
+            "cyclonedx-json" => Ok(Format::CycloneDxJson),
+            "spdx-json" => Ok(Format::SpdxJson),
+            "licenses" => Ok(Format::Licenses),
+            "notices" => Ok(Format::Notices),
+            s => Err(format_err!("Unknown format: {:?}", s)),
+        }
+    }
+}
+
+/// What `--deny` rejects.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Deny {
+    /// Unused dependencies declared in the manifests of the members.
+    Unused,
+}
+
+impl FromStr for Deny {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, failure::Error> {
+        match s {
+            "unused" => Ok(Deny::Unused),
+            s => Err(format_err!("Unknown lint: {:?}", s)),
+        }
+    }
 }
 
+/// The exit code for `--deny` and `--policy`.
+pub const EXIT_CODE_DENIED: i32 = 2;
+
 impl CargoLinked {
-    pub fn run(self, config: &mut cargo::Config, mut stdout: impl Write) -> CliResult {
-        let outcome = self.outcome(config)?;
-        let outcome = miniserde::json::to_string(&outcome);
+    pub fn run(mut self, config: &mut cargo::Config, mut stdout: impl Write) -> CliResult {
+        if self.fix {
+            // Every target that can see the dependencies needs to be analyzed.
+            self.all_targets = true;
+            let dry_run = self.dry_run;
+            let outcome = self.outcome(config)?;
+            fix(&outcome, dry_run, config, stdout)?;
+            return warn_denied(&outcome, None, config)
+                .map(drop)
+                .map_err(Into::into);
+        }
+        if self.deny.is_some() || self.policy.is_some() {
+            let deny = self.deny;
+            let denylist = self
+                .policy
+                .as_ref()
+                .map(|p| policy::Denylist::read(p))
+                .transpose()?;
+            // Every target that can see the dependencies needs to be analyzed, and the denylist
+            // is checked against the `lib`s and the `bin`s.
+            self.all_targets = true;
+            let outcome = self.outcome(config)?;
+            return check(&outcome, deny, denylist.as_ref(), config);
+        }
+
+        let why = self
+            .why
+            .as_ref()
+            .map(|s| PackageIdSpec::parse(s))
+            .transpose()?;
+        let (format, collapse) = (self.format, self.collapse);
+        let artifact = self.artifact.clone();
+        let mut outcome = self.outcome(config)?;
+        if let Some(artifact) = artifact {
+            outcome.attribute_sizes(&size::sizes_by_crate(&artifact)?)?;
+        }
+        let color = match config.shell().color_choice() {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::CargoAuto => io::stdout().is_terminal(),
+        };
+        let output = match (&why, format) {
+            (Some(why), _) => explain(&outcome, why)?,
+            (None, Format::Json) => serde_json::to_string(&ser::Outcome::from(&outcome))
+                .map_err(failure::Error::from)?,
+            (None, Format::Human) => human::tree(&outcome, color),
+            (None, Format::Table) => human::table(&outcome, color),
+            (None, Format::Dot) => dot::graph(&outcome, collapse),
+            (None, Format::CycloneDxJson) => sbom::cyclonedx(&outcome).to_string(),
+            (None, Format::SpdxJson) => {
+                let created = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+                sbom::spdx(&outcome, &created).to_string()
+            }
+            (None, Format::Licenses) => license::report(&outcome, color),
+            (None, Format::Notices) => license::notices(&outcome)?,
+        };
         stdout
-            .write_all(outcome.as_ref())
+            .write_all(output.as_ref())
             .and_then(|()| stdout.flush())
-            .map_err(failure::Error::from)
+            .map_err(failure::Error::from)?;
+        warn_denied(&outcome, None, config)
+            .map(drop)
             .map_err(Into::into)
     }
 
-    pub fn outcome(self, config: &mut cargo::Config) -> CargoResult<LinkedPackages> {
+    pub fn outcome(self, config: &mut cargo::Config) -> CargoResult<LinkedOutcome> {
         let Self {
             demonstrate,
+            workspace,
+            package,
+            exclude,
+            all_targets,
             lib,
             debug,
             all_features,
@@ -169,14 +450,25 @@ impl CargoLinked {
             frozen,
             locked,
             offline,
+            verbose,
             jobs,
             bin,
             example,
             test,
             bench,
+            target: triples,
             features,
             manifest_path,
             color,
+            why: _,
+            fix: _,
+            dry_run: _,
+            backend,
+            format: _,
+            collapse: _,
+            deny: _,
+            policy: _,
+            artifact: _,
         } = self;
 
         let manifest_path = manifest_path.map(Ok).unwrap_or_else(|| {
@@ -188,41 +480,78 @@ impl CargoLinked {
             frozen,
             locked,
             offline,
+            verbose,
             modify_target_dir: |d| d.join("cargo_linked").join("check"),
         }
         .configure(config)?;
 
         let ws = Workspace::new(&manifest_path, config)?;
+        let spec = Packages::from_flags(workspace, exclude, package)?;
 
         let (packages, resolve) = {
             let ws_resolve = cargo::ops::resolve_ws_with_opts(
                 &ws,
                 ResolveOpts::new(true, &features, all_features, !no_default_features),
-                &Packages::All.to_package_id_specs(&ws)?,
+                &spec.to_package_id_specs(&ws)?,
             )?;
             (ws_resolve.pkg_set, ws_resolve.targeted_resolve)
         };
 
-        let (compile_opts, target) = util::CompileOptionsForSingleTarget {
-            ws: &ws,
-            jobs: &jobs,
-            lib,
-            bin: &bin,
-            example: &example,
-            test: &test,
-            bench: &bench,
-            release: !debug,
-            features: &features,
-            all_features,
-            no_default_features,
-            manifest_path: &manifest_path,
-            compile_mode: CompileMode::Check {
-                test: test.is_some(),
-            },
+        let host = ws.config().load_global_rustc(Some(&ws))?.host.to_string();
+        let triples = requested_triples(&triples, &host);
+
+        let mut platforms = btreemap!();
+        for &(triple, key) in &triples {
+            let mut members = btreemap!();
+            for member in spec.get_packages(&ws)? {
+                let (compile_opts, targets) = util::CompileOptionsForTargets {
+                    ws: &ws,
+                    package: member,
+                    jobs: &jobs,
+                    all_targets,
+                    lib,
+                    bin: &bin,
+                    example: &example,
+                    test: &test,
+                    bench: &bench,
+                    triple,
+                    release: !debug,
+                    features: &features,
+                    all_features,
+                    no_default_features,
+                    manifest_path: &manifest_path,
+                    compile_mode: CompileMode::Check {
+                        test: test.is_some(),
+                    },
+                }
+                .compile_options_for_targets()?;
+
+                let mut outcomes = LinkedPackages::find(
+                    &ws,
+                    member,
+                    &packages,
+                    &resolve,
+                    &compile_opts,
+                    &targets,
+                    backend,
+                )?;
+                let outcome = if all_targets {
+                    LinkedPackages::with_targets(outcomes)
+                } else {
+                    outcomes
+                        .remove(targets[0])
+                        .ok_or_else(|| format_err!("`{}` was not compiled", targets[0]))?
+                };
+                members.insert(member.package_id(), outcome);
+            }
+            platforms.insert(key.to_owned(), LinkedWorkspace::new(members));
         }
-        .compile_options_for_single_target()?;
-
-        let outcome = LinkedPackages::find(&ws, &packages, &resolve, &compile_opts, target)?;
+        let metadata =
+            LinkedPackagesMetadata::new(&packages.get_many(packages.package_ids())?, &resolve);
+        let outcome = LinkedOutcome {
+            platforms,
+            metadata,
+        };
 
         if demonstrate {
             drop(packages);
@@ -233,38 +562,242 @@ impl CargoLinked {
                 frozen,
                 locked,
                 offline,
+                verbose,
                 modify_target_dir: |d| d.parent().unwrap().join("demonstrate"),
             }
             .configure(config)?;
 
             let ws = Workspace::new(&manifest_path, config)?;
 
-            let (compile_opts, _) = util::CompileOptionsForSingleTarget {
-                ws: &ws,
-                jobs: &jobs,
-                lib,
-                bin: &bin,
-                example: &example,
-                test: &test,
-                bench: &bench,
-                release: !debug,
-                features: &features,
-                all_features,
-                no_default_features,
-                manifest_path: &manifest_path,
-                compile_mode: CompileMode::Build,
-            }
-            .compile_options_for_single_target()?;
+            for &(triple, key) in &triples {
+                let platform = &outcome.platforms[key];
+                for member in spec.get_packages(&ws)? {
+                    let (compile_opts, _) = util::CompileOptionsForTargets {
+                        ws: &ws,
+                        package: member,
+                        jobs: &jobs,
+                        all_targets,
+                        lib,
+                        bin: &bin,
+                        example: &example,
+                        test: &test,
+                        bench: &bench,
+                        triple,
+                        release: !debug,
+                        features: &features,
+                        all_features,
+                        no_default_features,
+                        manifest_path: &manifest_path,
+                        compile_mode: CompileMode::Build,
+                    }
+                    .compile_options_for_targets()?;
 
-            self::demonstrate(&ws, &compile_opts, outcome.used.clone())?;
+                    // Ignored packages are not judged, so they are kept.
+                    let linked = &platform.members[&member.package_id()];
+                    let used = &linked.used | &linked.ignored;
+                    self::demonstrate(&ws, member.package_id(), &compile_opts, used)?;
+                }
+            }
         }
 
         Ok(outcome)
     }
 }
 
+/// Pairs the `--target`s to pass to cargo with the keys of `LinkedOutcome::platforms`, dropping
+/// the duplicates. The host is analyzed without `--target` if none is given.
+fn requested_triples<'a>(triples: &'a [String], host: &'a str) -> Vec<(Option<&'a str>, &'a str)> {
+    if triples.is_empty() {
+        return vec![(None, host)];
+    }
+    let mut seen = hashset!();
+    triples
+        .iter()
+        .filter(|t| seen.insert(*t))
+        .map(|t| (Some(&**t), &**t))
+        .collect()
+}
+
+fn explain(outcome: &LinkedOutcome, spec: &PackageIdSpec) -> CargoResult<String> {
+    let mut ret = "".to_owned();
+    for (triple, platform) in &outcome.platforms {
+        for (member, linked) in &platform.members {
+            for &id in linked.used.iter().filter(|&&id| spec.matches(id)) {
+                let path = linked
+                    .why(id)
+                    .expect("`used` and `reached_via` should be consistent");
+                ret += &format!("`{}` ({})\n", member, triple);
+                for (depth, (edge, to)) in path.into_iter().enumerate() {
+                    (0..depth).for_each(|_| ret += "     ");
+                    ret += &format!(
+                        "└─── {} (`{}`): `{}`\n",
+                        edge.target, edge.extern_crate_name, to,
+                    );
+                }
+            }
+        }
+    }
+    if ret.is_empty() {
+        return Err(format_err!("`{}` is not linked", spec));
+    }
+    Ok(ret)
+}
+
+fn check(
+    outcome: &LinkedOutcome,
+    deny: Option<Deny>,
+    denylist: Option<&policy::Denylist>,
+    config: &cargo::Config,
+) -> CliResult {
+    let mut problems = 0;
+    if let (Some(Deny::Unused), LinkedVerdict::Unused(unused)) = (deny, outcome.verdict()) {
+        for entry in &unused {
+            let location = match (entry.line, entry.column) {
+                (Some(line), Some(column)) => format!(":{}:{}", line, column),
+                _ => "".to_owned(),
+            };
+            config.shell().warn(format!(
+                "unused dependency `{}` ({}) at {}{}",
+                entry.name_in_toml,
+                entry.package,
+                entry.manifest_path.display(),
+                location,
+            ))?;
+        }
+        problems += unused.len();
+    }
+    // The `never` policies are always checked.
+    problems += warn_denied(outcome, denylist, config)?;
+    if problems == 0 {
+        return Ok(());
+    }
+    let err = format_err!("denied {} problem(s)", problems);
+    Err(CliError::new(err, EXIT_CODE_DENIED))
+}
+
+/// Warns about the packages which the `never` policies or the denylist reject, returning the
+/// number of them.
+fn warn_denied(
+    outcome: &LinkedOutcome,
+    denylist: Option<&policy::Denylist>,
+    config: &cargo::Config,
+) -> CargoResult<usize> {
+    let mut violations = policy::forbidden(outcome);
+    if let Some(denylist) = denylist {
+        violations.extend(denylist.check(outcome));
+    }
+    for violation in &violations {
+        config.shell().warn(format!(
+            "denied `{}` ({}) is linked for {}: {}",
+            violation.package,
+            violation.reason,
+            violation.triple,
+            violation.witness(),
+        ))?;
+    }
+    Ok(violations.len())
+}
+
+fn fix(
+    outcome: &LinkedOutcome,
+    dry_run: bool,
+    config: &cargo::Config,
+    mut stdout: impl Write,
+) -> CargoResult<()> {
+    // Doctests are not analyzed.
+    for entry in outcome.direct() {
+        if entry.active && !entry.used && entry.kind == dependency::Kind::Development {
+            config.shell().warn(format!(
+                "Skipping `{}`: dev-dependencies may be used by the doctests",
+                entry.name_in_toml,
+            ))?;
+        }
+    }
+    // The same entries as `--deny unused` rejects.
+    let mut removals = BTreeMap::<_, Vec<_>>::new();
+    if let LinkedVerdict::Unused(unused) = outcome.verdict() {
+        for entry in unused {
+            removals
+                .entry(entry.manifest_path.clone())
+                .or_default()
+                .push(entry);
+        }
+    }
+
+    for (manifest_path, entries) in removals {
+        let text = std::fs::read_to_string(&manifest_path)
+            .map_err(|e| format_err!("Failed to read {}: {}", manifest_path.display(), e))?;
+        // Optional dependencies are features, which the other members may enable.
+        let ws = Workspace::new(&manifest_path, config)?;
+        let package = ws.current()?.name();
+        let others = ws
+            .members()
+            .filter(|p| p.manifest_path() != manifest_path)
+            .map(|p| {
+                let path = p.manifest_path();
+                std::fs::read_to_string(path)
+                    .map(|text| (path.to_owned(), text))
+                    .map_err(|e| format_err!("Failed to read {}: {}", path.display(), e))
+            })
+            .collect::<CargoResult<Vec<_>>>()?;
+        let mut keys = vec![];
+        'entries: for entry in entries {
+            let features = manifest::features_referring_to(&text, &entry.name_in_toml)?;
+            if !features.is_empty() {
+                config.shell().warn(format!(
+                    "Not removing `{}`: referred by the feature(s) {:?}",
+                    entry.name_in_toml, features,
+                ))?;
+                continue;
+            }
+            for (other_path, other_text) in &others {
+                let places =
+                    manifest::features_enabling(other_text, &package, &entry.name_in_toml)?;
+                if !places.is_empty() {
+                    config.shell().warn(format!(
+                        "Not removing `{}`: enabled by {:?} of {}",
+                        entry.name_in_toml,
+                        places,
+                        other_path.display(),
+                    ))?;
+                    continue 'entries;
+                }
+            }
+            keys.push(manifest::DependencyKey {
+                kind: entry.kind,
+                platform: entry.platform.as_ref().map(|p| p.parse()).transpose()?,
+                name_in_toml: entry.name_in_toml.clone(),
+            });
+            if !dry_run {
+                config.shell().status(
+                    "Removing",
+                    format!("`{}` from {}", entry.name_in_toml, manifest_path.display()),
+                )?;
+            }
+        }
+        if keys.is_empty() {
+            continue;
+        }
+        let fixed = manifest::remove_dependencies(&text, &keys)?;
+        if dry_run {
+            let path = manifest_path.display().to_string();
+            let diff = similar::TextDiff::from_lines(&text, &fixed)
+                .unified_diff()
+                .header(&path, &path)
+                .to_string();
+            stdout.write_all(diff.as_ref())?;
+        } else {
+            std::fs::write(&manifest_path, fixed)
+                .map_err(|e| format_err!("Failed to write {}: {}", manifest_path.display(), e))?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
 fn demonstrate(
     ws: &Workspace,
+    current: PackageId,
     compile_opts: &CompileOptions,
     used: BTreeSet<PackageId>,
 ) -> CargoResult<()> {
@@ -325,12 +858,11 @@ fn demonstrate(
     };
     cargo::ops::clean(ws, &clean_opts)?;
 
-    let current = ws.current()?.package_id();
     let exec: Arc<dyn Executor + 'static> = Arc::new(Exec { current, used });
     cargo::ops::compile_with_exec(ws, &compile_opts, &exec).map(|_| ())
 }
 
-#[derive(Default, serde::Deserialize)]
+#[derive(Default, serde::Deserialize, serde::Serialize)]
 #[serde(transparent)]
 struct Cache(Vec<CacheValue>);
 
@@ -363,25 +895,33 @@ impl Index<&'_ CacheKey> for Cache {
     }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 struct CacheValue {
     key: CacheKey,
     used_packages: BTreeMap<PackageId, CacheUsedPackages>,
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, serde::Deserialize, miniserde::Serialize)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, serde::Deserialize, serde::Serialize)]
 struct CacheKey {
     profile_kind: String,
+    target: Option<String>,
 }
 
 impl CacheKey {
-    fn new(profile_kind: &ProfileKind) -> Self {
+    fn new(profile_kind: &ProfileKind, requested_kind: CompileKind) -> Self {
         let profile_kind = profile_kind.name().to_owned();
-        Self { profile_kind }
+        let target = match requested_kind {
+            CompileKind::Host => None,
+            CompileKind::Target(target) => Some(target.rustc_target().to_owned()),
+        };
+        Self {
+            profile_kind,
+            target,
+        }
     }
 }
 
-#[derive(Default, Debug, serde::Deserialize)]
+#[derive(Clone, Default, Debug, serde::Deserialize, serde::Serialize)]
 struct CacheUsedPackages {
     lib: Option<BTreeSet<PackageId>>,
     bin: BTreeMap<String, BTreeSet<PackageId>>,
@@ -390,6 +930,12 @@ struct CacheUsedPackages {
     example_lib: BTreeMap<String, BTreeSet<PackageId>>,
     example_bin: BTreeMap<String, BTreeSet<PackageId>>,
     custom_build: Option<BTreeSet<PackageId>>,
+    /// The unit tests of the `lib`.
+    #[serde(default)]
+    lib_test: Option<BTreeSet<PackageId>>,
+    /// The unit tests of the `bin`s.
+    #[serde(default)]
+    bin_test: BTreeMap<String, BTreeSet<PackageId>>,
 }
 
 impl CacheUsedPackages {
@@ -432,143 +978,735 @@ impl CacheUsedPackages {
             TargetKind::CustomBuild => self.custom_build = Some(val),
         }
     }
+
+    /// The unit tests, which share their `Target`s with the `lib` and the `bin`s.
+    fn get_unit_test<'a>(&'a self, target: &Target) -> Option<&'a BTreeSet<PackageId>> {
+        match target.kind() {
+            TargetKind::Lib(_) => self.lib_test.as_ref(),
+            TargetKind::Bin => self.bin_test.get(target.name()),
+            _ => None,
+        }
+    }
+
+    fn insert_unit_test<I: IntoIterator<Item = P>, P: Borrow<PackageId>>(
+        &mut self,
+        target: &Target,
+        packages: I,
+    ) {
+        let val = packages.into_iter().map(|p| *p.borrow()).collect();
+        match target.kind() {
+            TargetKind::Lib(_) => self.lib_test = Some(val),
+            TargetKind::Bin => {
+                self.bin_test.insert(target.name().to_owned(), val);
+            }
+            _ => panic!("{} does not have unit tests", target.description_named()),
+        }
+    }
 }
 
-#[derive(Debug, Default)]
-pub struct LinkedPackages {
-    pub used: BTreeSet<PackageId>,
-    pub unused: LinkedPackagesUnused,
+/// `[workspace.metadata.cargo-linked]` of the root manifest and
+/// `[package.metadata.cargo-linked]` of the member, combined.
+#[derive(Default, Debug)]
+struct Policy {
+    /// Used wherever they are dependencies, e.g. for `#[global_allocator]`s or link arguments.
+    always_used: Vec<PackageIdSpec>,
+    /// Not reported.
+    ignore: Vec<PackageIdSpec>,
+    /// Must not be linked into the artifacts.
+    never: Vec<PackageIdSpec>,
 }
 
-impl LinkedPackages {
-    fn find(
-        ws: &Workspace,
-        packages: &PackageSet,
-        resolve: &Resolve,
-        compile_opts: &CompileOptions,
-        target: &Target,
-    ) -> CargoResult<Self> {
-        let current = ws.current()?;
+impl Policy {
+    fn read(ws: &Workspace, member_manifest: &str) -> CargoResult<Self> {
+        let mut policy = Self::default();
+        let root_manifest_path = ws.root().join("Cargo.toml");
+        let root_manifest = std::fs::read_to_string(&root_manifest_path).map_err(|err| {
+            format_err!("Failed to read {}: {}", root_manifest_path.display(), err)
+        })?;
+        for metadata in &[
+            manifest::linked_metadata(&root_manifest, "workspace")?,
+            manifest::linked_metadata(member_manifest, "package")?,
+        ] {
+            for (specs, strs) in &mut [
+                (&mut policy.always_used, &metadata.always_used),
+                (&mut policy.ignore, &metadata.ignore),
+                (&mut policy.never, &metadata.never),
+            ] {
+                for s in strs.iter() {
+                    specs.push(PackageIdSpec::parse(s)?);
+                }
+            }
+        }
+        Ok(policy)
+    }
 
-        let all_ids =
-            cargo::ops::resolve_ws(ws).map(|(ps, _)| ps.package_ids().collect::<HashSet<_>>())?;
+    fn is_always_used(&self, id: PackageId) -> bool {
+        self.always_used.iter().any(|s| s.matches(id))
+    }
 
-        let packages = packages
-            .get_many(packages.package_ids())?
-            .into_iter()
-            .map(|p| (p.package_id(), p))
-            .collect::<BTreeMap<_, _>>();
+    fn ignores(&self, id: PackageId) -> bool {
+        self.ignore.iter().any(|s| s.matches(id))
+    }
 
-        let target = target.clone();
+    fn forbids(&self, id: PackageId) -> bool {
+        self.never.iter().any(|s| s.matches(id))
+    }
 
-        let unnecessary_dev_deps =
-            if target.is_test() || target.is_example() || target.is_custom_build() {
-                hashset!()
-            } else {
-                let mut dev_removed = hashset!(&current);
-                let mut cur = dev_removed.clone();
-                loop {
-                    let mut next = hashset!();
-                    for from_pkg in cur {
-                        for (to_id, deps) in resolve.deps(from_pkg.package_id()) {
-                            if deps
-                                .iter()
-                                .any(|d| d.kind() != dependency::Kind::Development)
-                            {
-                                let to_pkg = &packages[&to_id];
-                                if dev_removed.insert(to_pkg) {
-                                    next.insert(to_pkg);
-                                }
-                            }
-                        }
+    /// Adds the `always_used` packages to the units depending on them.
+    fn with_always_used(
+        &self,
+        used_packages: &BTreeMap<PackageId, CacheUsedPackages>,
+        extern_crate_names: &HashMap<PackageId, HashMap<Target, HashMap<PackageId, String>>>,
+    ) -> BTreeMap<PackageId, CacheUsedPackages> {
+        let mut used_packages = used_packages.clone();
+        for (from, names) in extern_crate_names {
+            if let Some(slots) = used_packages.get_mut(from) {
+                for (target, names) in names {
+                    let always_used = names.keys().cloned().filter(|&id| self.is_always_used(id));
+                    if let Some(used) = slots.get(target) {
+                        let used = used
+                            .iter()
+                            .cloned()
+                            .chain(always_used.clone())
+                            .collect::<BTreeSet<_>>();
+                        slots.insert(target, used);
                     }
-                    cur = next;
-                    if cur.is_empty() {
-                        break;
+                    if let Some(used) = slots.get_unit_test(target) {
+                        let used = used
+                            .iter()
+                            .cloned()
+                            .chain(always_used)
+                            .collect::<BTreeSet<_>>();
+                        slots.insert_unit_test(target, used);
                     }
                 }
-                packages
-                    .values()
-                    .cloned()
-                    .filter(|p| !dev_removed.contains(p))
-                    .map(Package::package_id)
-                    .collect()
-            };
+            }
+        }
+        used_packages
+    }
+}
 
-        let extern_crate_names = packages
-            .values()
-            .map(|from_pkg| {
-                let resolve_names = |filter: fn(_) -> _| -> CargoResult<HashMap<_, _>> {
-                    resolve
-                        .deps(from_pkg.package_id())
-                        .flat_map(|(to_id, deps)| deps.iter().map(move |d| (d, to_id)))
-                        .filter(|&(d, _)| filter(d.kind()))
-                        .map(|(_, to_id)| {
-                            let to_lib = packages
-                                .get(&to_id)
-                                .unwrap_or_else(|| panic!("could not find `{}`", to_id))
-                                .targets()
-                                .iter()
-                                .find(|t| t.is_lib())
-                                .unwrap_or_else(|| {
-                                    panic!("`{}` does not have any `lib` target", to_id)
-                                });
-                            let extern_crate_name =
-                                resolve.extern_crate_name(from_pkg.package_id(), to_id, to_lib)?;
-                            Ok((to_id, extern_crate_name))
-                        })
-                        .collect()
-                };
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum UsedBy {
+    Root,
+    Lib,
+    CustomBuild,
+}
 
-                let normal_dev = resolve_names(|k| k != dependency::Kind::Build)?;
-                let build = resolve_names(|k| k == dependency::Kind::Build)?;
-                let self_lib_name = from_pkg
-                    .targets()
-                    .iter()
-                    .find(|t| t.is_lib())
-                    .map(|lib| {
-                        let id = from_pkg.package_id();
-                        resolve.extern_crate_name(id, id, lib)
-                    })
-                    .transpose()?;
+/// Walks the `lib`s and the build scripts from the `target` of `root`.
+///
+/// Each package is mapped to the package and the target it is first reached from.
+fn used_transitively(
+    used_packages: &BTreeMap<PackageId, CacheUsedPackages>,
+    root: PackageId,
+    target: &Target,
+) -> CargoResult<BTreeMap<PackageId, (PackageId, UsedBy)>> {
+    let mut used = used_packages
+        .get(&root)
+        .and_then(|p| p.get(target))
+        .ok_or_else(|| format_err!("`{}` of `{}` is not cached", target, root))?
+        .iter()
+        .map(|&id| (id, (root, UsedBy::Root)))
+        .collect::<BTreeMap<_, _>>();
+    let mut cur = used.keys().cloned().collect::<BTreeSet<_>>();
+    while !cur.is_empty() {
+        let mut next = btreeset!();
+        for id in cur {
+            let deps = used_packages
+                .get(&id)
+                .ok_or_else(|| format_err!("`{}` is not cached", id))?;
+            let lib = deps
+                .lib
+                .as_ref()
+                .ok_or_else(|| format_err!("the `lib` of `{}` is not cached", id))?
+                .iter()
+                .map(|&dep| (dep, UsedBy::Lib));
+            let custom_build = deps
+                .custom_build
+                .iter()
+                .flatten()
+                .map(|&dep| (dep, UsedBy::CustomBuild));
+            for (dep, used_by) in lib.chain(custom_build) {
+                if let btree_map::Entry::Vacant(entry) = used.entry(dep) {
+                    entry.insert((id, used_by));
+                    next.insert(dep);
+                }
+            }
+        }
+        cur = next;
+    }
+    Ok(used)
+}
 
-                let extern_crate_names = from_pkg
-                    .targets()
-                    .iter()
-                    .map(|from_target| {
-                        let mut extern_crate_names = if from_target.is_custom_build() {
-                            build.clone()
-                        } else {
-                            normal_dev.clone()
-                        };
-                        if !(from_target.is_lib() || from_target.is_custom_build()) {
-                            if let Some(self_lib_name) = self_lib_name.clone() {
-                                extern_crate_names.insert(from_pkg.package_id(), self_lib_name);
-                            }
-                        }
-                        (from_target.clone(), extern_crate_names)
-                    })
-                    .collect::<HashMap<_, _>>();
+/// Tags each package in `used_transitively` by how it ends up in the artifact of the `target`.
+///
+/// Proc-macro crates and their dependencies only run on the host, and packages reached only
+/// through a `custom_build` slot are `Build`.
+fn used_as(
+    used_packages: &BTreeMap<PackageId, CacheUsedPackages>,
+    root: PackageId,
+    target: &Target,
+    proc_macros: &HashSet<PackageId>,
+) -> CargoResult<BTreeMap<PackageId, UsedAs>> {
+    let slots = |id: &PackageId| {
+        used_packages
+            .get(id)
+            .ok_or_else(|| format_err!("`{}` is not cached", id))
+    };
+    let linked_as = if target.is_custom_build() {
+        UsedAs::Build
+    } else if target.proc_macro() {
+        UsedAs::ProcMacro
+    } else {
+        UsedAs::Runtime
+    };
 
-                Ok((from_pkg.package_id(), extern_crate_names))
-            })
-            .collect::<CargoResult<HashMap<_, _>>>()?;
+    let mut used_as = btreemap!();
+    let mut proc_macro_roots = vec![];
+    let mut build_roots = vec![];
+    let mut stack = used_packages
+        .get(&root)
+        .and_then(|p| p.get(target))
+        .ok_or_else(|| format_err!("`{}` of `{}` is not cached", target, root))?
+        .iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    while let Some(id) = stack.pop() {
+        if used_as.contains_key(&id) {
+            continue;
+        }
+        if linked_as == UsedAs::Runtime && proc_macros.contains(&id) {
+            proc_macro_roots.push(id);
+            continue;
+        }
+        used_as.insert(id, linked_as);
+        let slots = slots(&id)?;
+        stack.extend(slots.lib.iter().flatten());
+        build_roots.extend(slots.custom_build.iter().flatten());
+    }
 
-        let cache_file = ws
-            .target_dir()
-            .join("..")
+    let mut walk_host = |mut stack: Vec<PackageId>, tag: UsedAs| -> CargoResult<()> {
+        while let Some(id) = stack.pop() {
+            if used_as.contains_key(&id) {
+                continue;
+            }
+            used_as.insert(id, tag);
+            let slots = slots(&id)?;
+            stack.extend(slots.lib.iter().flatten());
+            stack.extend(slots.custom_build.iter().flatten());
+        }
+        Ok(())
+    };
+    walk_host(proc_macro_roots, UsedAs::ProcMacro)?;
+    walk_host(build_roots, UsedAs::Build)?;
+    Ok(used_as)
+}
+
+/// Walks the resolved graph from the `target` of `root` through the `lib`s and the build scripts.
+///
+/// Returns the extern crate names and the kinds of the edges walked, keyed by the dependencies.
+/// Development dependencies are only followed from the tests, the benches and the examples of
+/// `root`.
+fn target_edges(
+    packages: &BTreeMap<PackageId, &Package>,
+    resolve: &Resolve,
+    extern_crate_names: &HashMap<PackageId, HashMap<Target, HashMap<PackageId, String>>>,
+    root: PackageId,
+    target: &Target,
+) -> (
+    BTreeMap<PackageId, BTreeSet<String>>,
+    BTreeMap<PackageId, BTreeSet<dependency::Kind>>,
+) {
+    let (mut names, mut kinds) = (
+        BTreeMap::<_, BTreeSet<_>>::new(),
+        BTreeMap::<_, BTreeSet<_>>::new(),
+    );
+    let mut visited = hashset!();
+    let mut stack = vec![(root, target)];
+    while let Some((from, from_target)) = stack.pop() {
+        if !visited.insert((from, from_target)) {
+            continue;
+        }
+        let from_names = extern_crate_names
+            .get(&from)
+            .and_then(|names| names.get(from_target));
+        let follows = |kind| match kind {
+            dependency::Kind::Normal => !from_target.is_custom_build(),
+            dependency::Kind::Development => {
+                from == root && (target.is_test() || target.is_bench() || target.is_example())
+            }
+            dependency::Kind::Build => from_target.is_custom_build(),
+        };
+        for (to, deps) in resolve.deps(from) {
+            let deps = deps
+                .iter()
+                .filter(|d| follows(d.kind()))
+                .collect::<Vec<_>>();
+            if deps.is_empty() {
+                continue;
+            }
+            if let Some(name) = from_names.and_then(|names| names.get(&to)) {
+                names.entry(to).or_default().insert(name.clone());
+            }
+            let to_kinds = kinds.entry(to).or_default();
+            to_kinds.extend(deps.into_iter().map(Dependency::kind));
+            stack.extend(
+                packages[&to]
+                    .targets()
+                    .iter()
+                    .filter(|t| t.is_lib())
+                    .map(|t| (to, t)),
+            );
+        }
+        if !from_target.is_custom_build() {
+            // The bins, the tests, the benches and the examples use the `lib` of their own package.
+            if let Some(name) = from_names.and_then(|names| names.get(&from)) {
+                names.entry(from).or_default().insert(name.clone());
+                let lib = packages[&from].targets().iter().filter(|t| t.is_lib());
+                stack.extend(lib.map(|t| (from, t)));
+            }
+            let custom_build = packages[&from]
+                .targets()
+                .iter()
+                .filter(|t| t.is_custom_build());
+            stack.extend(custom_build.map(|t| (from, t)));
+        }
+    }
+    (names, kinds)
+}
+
+#[derive(Debug, Default)]
+pub struct LinkedOutcome {
+    /// Keyed by the target triples. The host triple is used if no `--target` is given.
+    pub platforms: BTreeMap<String, LinkedWorkspace>,
+    /// The fields of the resolved packages, which do not depend on the platforms.
+    pub metadata: LinkedPackagesMetadata,
+}
+
+impl LinkedOutcome {
+    /// The `direct` entries of the unions, merged across the platforms.
+    pub fn direct(&self) -> Vec<LinkedDirectDependency> {
+        let mut direct = Vec::<LinkedDirectDependency>::new();
+        for workspace in self.platforms.values() {
+            for entry in &workspace.union.direct {
+                match direct.iter_mut().find(|d| d.same_entry(entry)) {
+                    Some(d) => {
+                        d.active |= entry.active;
+                        d.used |= entry.used;
+                    }
+                    None => direct.push(entry.clone()),
+                }
+            }
+        }
+        direct
+    }
+
+    /// Checks the `direct` entries merged across the platforms.
+    pub fn verdict(&self) -> LinkedVerdict {
+        LinkedVerdict::new(self.direct())
+    }
+
+    /// The packages used at runtime on any of the platforms, which end up in the artifacts.
+    pub fn runtime(&self) -> BTreeSet<PackageId> {
+        let mut runtime = btreeset!();
+        for workspace in self.platforms.values() {
+            let union = &workspace.union;
+            runtime.extend(union.used.iter().filter(|id| {
+                !matches!(
+                    union.used_as.get(id),
+                    Some(UsedAs::ProcMacro) | Some(UsedAs::Build)
+                )
+            }));
+        }
+        runtime
+    }
+
+    /// Sets `size_bytes` from the sizes keyed by the crate names.
+    ///
+    /// The sizes come from the artifact of one platform, so there must be only one. Crate names
+    /// shared by several used packages are left unattributed.
+    pub fn attribute_sizes(&mut self, sizes: &BTreeMap<String, u64>) -> CargoResult<()> {
+        if self.platforms.len() > 1 {
+            return Err(format_err!(
+                "The sizes are for one platform, but {} platforms are analyzed",
+                self.platforms.len(),
+            ));
+        }
+        let lib_names = &self.metadata.lib_names;
+        for workspace in self.platforms.values_mut() {
+            let mut by_crate_name = BTreeMap::<_, Vec<_>>::new();
+            let ids = workspace
+                .members
+                .keys()
+                .chain(&workspace.union.used)
+                .collect::<BTreeSet<_>>();
+            for &id in ids {
+                let crate_name = match lib_names.get(&id) {
+                    Some(lib_name) => lib_name.clone(),
+                    None => id.name().replace('-', "_"),
+                };
+                by_crate_name.entry(crate_name).or_default().push(id);
+            }
+            let size_bytes = by_crate_name
+                .into_iter()
+                .flat_map(|(crate_name, ids)| match *ids {
+                    [id] => Some((id, *sizes.get(&crate_name)?)),
+                    _ => None,
+                })
+                .collect::<BTreeMap<_, _>>();
+            workspace.union.set_size_bytes(&size_bytes);
+            for linked in workspace.members.values_mut() {
+                linked.set_size_bytes(&size_bytes);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LinkedWorkspace {
+    pub members: BTreeMap<PackageId, LinkedPackages>,
+    pub union: LinkedPackages,
+}
+
+impl LinkedWorkspace {
+    fn new(members: BTreeMap<PackageId, LinkedPackages>) -> Self {
+        let union = LinkedPackages::union(members.values());
+        Self { members, union }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LinkedPackagesMetadata {
+    /// The `license` fields of the packages.
+    pub licenses: BTreeMap<PackageId, String>,
+    /// The `license-file` fields of the packages, joined to `package_roots`.
+    pub license_files: BTreeMap<PackageId, PathBuf>,
+    /// The directories containing the manifests of the packages.
+    pub package_roots: BTreeMap<PackageId, PathBuf>,
+    /// The names of the `lib` targets of the packages.
+    pub lib_names: BTreeMap<PackageId, String>,
+    /// The SHA-256 checksums of the packages recorded in `Cargo.lock`.
+    pub checksums: BTreeMap<PackageId, String>,
+}
+
+impl LinkedPackagesMetadata {
+    fn new(packages: &[&Package], resolve: &Resolve) -> Self {
+        Self {
+            licenses: packages
+                .iter()
+                .flat_map(|p| Some((p.package_id(), p.manifest().metadata().license.clone()?)))
+                .collect(),
+            license_files: packages
+                .iter()
+                .flat_map(|p| {
+                    let license_file = p.manifest().metadata().license_file.as_ref()?;
+                    Some((p.package_id(), p.root().join(license_file)))
+                })
+                .collect(),
+            package_roots: packages
+                .iter()
+                .map(|p| (p.package_id(), p.root().to_owned()))
+                .collect(),
+            lib_names: packages
+                .iter()
+                .flat_map(|p| {
+                    let lib = p.targets().iter().find(|t| t.is_lib())?;
+                    Some((p.package_id(), lib.crate_name()))
+                })
+                .collect(),
+            checksums: resolve
+                .checksums()
+                .iter()
+                .flat_map(|(&id, checksum)| Some((id, checksum.clone()?)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LinkedPackages {
+    pub used: BTreeSet<PackageId>,
+    pub unused: LinkedPackagesUnused,
+    /// Outcomes for each target, keyed by the kind and the name. Empty unless `--all-targets`.
+    pub targets: BTreeMap<String, BTreeMap<String, LinkedPackages>>,
+    /// How each package in `used` ends up in the artifact.
+    pub used_as: BTreeMap<PackageId, UsedAs>,
+    /// Packages excluded from `used` and `unused` by the `ignore` policy.
+    pub ignored: BTreeSet<PackageId>,
+    /// Packages in `used` at runtime which the `never` policy forbids.
+    pub forbidden: BTreeSet<PackageId>,
+    /// The bytes of the code and the data attributed to the packages. Only with `--artifact`.
+    pub size_bytes: BTreeMap<PackageId, u64>,
+    /// The edge each package in `used` is first reached through.
+    pub reached_via: BTreeMap<PackageId, LinkedEdge>,
+    /// The dependencies declared in the manifests of the members.
+    pub direct: Vec<LinkedDirectDependency>,
+    /// The names each package is referred to as by the packages depending on it, within the
+    /// dependency graph of the target.
+    pub extern_crate_names: BTreeMap<PackageId, BTreeSet<String>>,
+    /// The kinds of the dependencies on each package, within the dependency graph of the target.
+    pub dependency_kinds: BTreeMap<PackageId, BTreeSet<dependency::Kind>>,
+    /// The edges of the resolved graph, and whether each of them is used.
+    ///
+    /// Edges from unused packages are never used.
+    pub resolved_edges: BTreeMap<PackageId, BTreeMap<PackageId, bool>>,
+}
+
+/// How a used package ends up in the artifact.
+///
+/// Ordered from the most to the least shipped.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UsedAs {
+    /// Linked into the final artifact.
+    Runtime,
+    /// Only runs on the host as (a dependency of) a proc-macro crate.
+    ProcMacro,
+    /// Reached only through a `custom_build` unit.
+    Build,
+}
+
+/// A dependency which made `extern_crate_name` count as used.
+#[derive(Clone, Debug)]
+pub struct LinkedEdge {
+    pub from: PackageId,
+    /// `lib`, `custom-build`, or the root target such as `bin "name"`.
+    pub target: String,
+    pub extern_crate_name: String,
+    pub from_root: bool,
+}
+
+/// An entry of `[dependencies]`, `[dev-dependencies]` or `[build-dependencies]`.
+#[derive(Clone, Debug)]
+pub struct LinkedDirectDependency {
+    pub name_in_toml: String,
+    pub package: PackageId,
+    pub kind: dependency::Kind,
+    /// The `target.<platform>` table the entry is in.
+    pub platform: Option<String>,
+    pub manifest_path: PathBuf,
+    /// 1-based. `None` if the entry could not be found in the manifest.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Whether `platform` matches the target triple, or the host triple for `build`.
+    pub active: bool,
+    pub used: bool,
+}
+
+/// Whether the declared dependencies pass `--deny unused`.
+#[derive(Debug)]
+pub enum LinkedVerdict {
+    Clean,
+    /// The active, non-dev entries which are not used.
+    Unused(Vec<LinkedDirectDependency>),
+}
+
+impl LinkedVerdict {
+    /// `dev-dependencies` are not checked since the doctests are not analyzed.
+    fn new(direct: impl IntoIterator<Item = LinkedDirectDependency>) -> Self {
+        let unused = direct
+            .into_iter()
+            .filter(|d| d.active && !d.used && d.kind != dependency::Kind::Development)
+            .collect::<Vec<_>>();
+        if unused.is_empty() {
+            LinkedVerdict::Clean
+        } else {
+            LinkedVerdict::Unused(unused)
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        match self {
+            LinkedVerdict::Clean => true,
+            LinkedVerdict::Unused(_) => false,
+        }
+    }
+}
+
+impl LinkedDirectDependency {
+    fn same_entry(&self, other: &Self) -> bool {
+        (
+            &self.manifest_path,
+            self.kind,
+            &self.platform,
+            &self.name_in_toml,
+            self.package,
+        ) == (
+            &other.manifest_path,
+            other.kind,
+            &other.platform,
+            &other.name_in_toml,
+            other.package,
+        )
+    }
+}
+
+impl LinkedPackages {
+    fn find(
+        ws: &Workspace,
+        current: &Package,
+        packages: &PackageSet,
+        resolve: &Resolve,
+        compile_opts: &CompileOptions,
+        targets: &[&Target],
+        backend: Backend,
+    ) -> CargoResult<BTreeMap<Target, Self>> {
+        let all_ids =
+            cargo::ops::resolve_ws(ws).map(|(ps, _)| ps.package_ids().collect::<HashSet<_>>())?;
+
+        let packages = packages
+            .get_many(packages.package_ids())?
+            .into_iter()
+            .map(|p| (p.package_id(), p))
+            .collect::<BTreeMap<_, _>>();
+
+        let dev_only = {
+            let mut dev_removed = hashset!(&current);
+            let mut cur = dev_removed.clone();
+            loop {
+                let mut next = hashset!();
+                for from_pkg in cur {
+                    for (to_id, deps) in resolve.deps(from_pkg.package_id()) {
+                        if deps
+                            .iter()
+                            .any(|d| d.kind() != dependency::Kind::Development)
+                        {
+                            let to_pkg = &packages[&to_id];
+                            if dev_removed.insert(to_pkg) {
+                                next.insert(to_pkg);
+                            }
+                        }
+                    }
+                }
+                cur = next;
+                if cur.is_empty() {
+                    break;
+                }
+            }
+            packages
+                .values()
+                .cloned()
+                .filter(|p| !dev_removed.contains(p))
+                .map(Package::package_id)
+                .collect::<HashSet<_>>()
+        };
+
+        let extern_crate_names = packages
+            .values()
+            .map(|from_pkg| {
+                let resolve_names = |filter: fn(_) -> _| -> CargoResult<HashMap<_, _>> {
+                    resolve
+                        .deps(from_pkg.package_id())
+                        .flat_map(|(to_id, deps)| deps.iter().map(move |d| (d, to_id)))
+                        .filter(|&(d, _)| filter(d.kind()))
+                        .map(|(_, to_id)| {
+                            let to_lib = packages
+                                .get(&to_id)
+                                .unwrap_or_else(|| panic!("could not find `{}`", to_id))
+                                .targets()
+                                .iter()
+                                .find(|t| t.is_lib())
+                                .unwrap_or_else(|| {
+                                    panic!("`{}` does not have any `lib` target", to_id)
+                                });
+                            let extern_crate_name =
+                                resolve.extern_crate_name(from_pkg.package_id(), to_id, to_lib)?;
+                            Ok((to_id, extern_crate_name))
+                        })
+                        .collect()
+                };
+
+                let normal_dev = resolve_names(|k| k != dependency::Kind::Build)?;
+                let build = resolve_names(|k| k == dependency::Kind::Build)?;
+                let self_lib_name = from_pkg
+                    .targets()
+                    .iter()
+                    .find(|t| t.is_lib())
+                    .map(|lib| {
+                        let id = from_pkg.package_id();
+                        resolve.extern_crate_name(id, id, lib)
+                    })
+                    .transpose()?;
+
+                let extern_crate_names = from_pkg
+                    .targets()
+                    .iter()
+                    .map(|from_target| {
+                        let mut extern_crate_names = if from_target.is_custom_build() {
+                            build.clone()
+                        } else {
+                            normal_dev.clone()
+                        };
+                        if !(from_target.is_lib() || from_target.is_custom_build()) {
+                            if let Some(self_lib_name) = self_lib_name.clone() {
+                                extern_crate_names.insert(from_pkg.package_id(), self_lib_name);
+                            }
+                        }
+                        (from_target.clone(), extern_crate_names)
+                    })
+                    .collect::<HashMap<_, _>>();
+
+                Ok((from_pkg.package_id(), extern_crate_names))
+            })
+            .collect::<CargoResult<HashMap<_, _>>>()?;
+
+        let manifest_text = std::fs::read_to_string(current.manifest_path()).map_err(|err| {
+            format_err!(
+                "Failed to read {}: {}",
+                current.manifest_path().display(),
+                err
+            )
+        })?;
+        let locations = manifest::locate_dependencies(&manifest_text)?;
+        let policy = Policy::read(ws, &manifest_text)?;
+
+        let rustc = ws.config().load_global_rustc(Some(ws))?;
+        let requested_kind = compile_opts.build_config.requested_kind;
+        let (target_triple, target_cfg) = (
+            match requested_kind {
+                CompileKind::Host => rustc.host.to_string(),
+                CompileKind::Target(target) => target.short_name().to_owned(),
+            },
+            TargetInfo::new(ws.config(), requested_kind, &rustc, requested_kind)?,
+        );
+        let host_cfg = TargetInfo::new(ws.config(), requested_kind, &rustc, CompileKind::Host)?;
+        let backend = match backend {
+            Backend::Auto | Backend::Lint
+                if process::supports_unused_crate_dependencies(&rustc) =>
+            {
+                backend
+            }
+            Backend::Auto | Backend::Trial => Backend::Trial,
+            Backend::Lint => {
+                return Err(format_err!(
+                    "`--backend lint` requires a rustc which supports `--force-warn \
+                     unused-crate-dependencies`",
+                ));
+            }
+        };
+
+        let cache_file = ws
+            .target_dir()
+            .join("..")
             .open_rw("cache.json", ws.config(), "msg?")?;
         let mut cache_file = JsonFileLock::<Cache>::from(cache_file);
         let mut cache = cache_file.read()?;
-        let cache_key = CacheKey::new(&compile_opts.build_config.profile_kind);
+        let cache_key = CacheKey::new(
+            &compile_opts.build_config.profile_kind,
+            compile_opts.build_config.requested_kind,
+        );
 
         let store = Arc::new(Mutex::new(ExecStore::new(
             cache.take_or_default(&cache_key),
         )));
         let exec: Arc<dyn Executor + 'static> = Arc::new(Exec {
-            target: target.clone(),
-            extern_crate_names,
+            extern_crate_names: extern_crate_names.clone(),
             supports_color: ws.config().shell().supports_color(),
+            verbose: ws.config().shell().verbosity() == Verbosity::Verbose,
+            backend,
+            host_cfg: host_cfg.cfg().to_vec(),
+            target_cfg: target_cfg.cfg().to_vec(),
             store: store.clone(),
         });
         cargo::ops::compile_with_exec(ws, compile_opts, &exec)?;
@@ -584,58 +1722,264 @@ impl LinkedPackages {
 
         cache.insert(cache_key.clone(), used_packages);
         cache_file.write(&cache)?;
+        let used_packages = policy.with_always_used(&cache[&cache_key], &extern_crate_names);
+
+        // Targets skipped by cargo (e.g. for `required-features`) are not reported.
+        let compiled = all_targets
+            .get(&current.package_id())
+            .cloned()
+            .unwrap_or_default();
+
+        let proc_macros = all_targets
+            .iter()
+            .filter(|(_, targets)| targets.iter().any(Target::proc_macro))
+            .map(|(&id, _)| id)
+            .collect::<HashSet<_>>();
 
-        let mut outcome = Self::default();
-        outcome.used = {
-            let mut used = cache[&cache_key]
-                .get(&current.package_id())
-                .unwrap()
-                .bin
-                .get(&target.name().to_owned())
-                .unwrap()
-                .clone();
-            let mut cur = used.clone();
-            while !cur.is_empty() {
-                let mut next = btreeset!();
-                for cur in cur {
-                    for dep in cache[&cache_key][&cur]
-                        .lib
-                        .as_ref()
-                        .unwrap()
+        let mut outcomes = btreemap!();
+        for &target in targets.iter().filter(|t| compiled.contains(t)) {
+            let unnecessary_dev_deps = if target.is_test()
+                || target.is_bench()
+                || target.is_example()
+                || target.is_custom_build()
+            {
+                hashset!()
+            } else {
+                dev_only.clone()
+            };
+
+            let mut outcome = Self::default();
+            let used = used_transitively(&used_packages, current.package_id(), target)?;
+            outcome.used = used.keys().cloned().collect();
+            outcome.used_as = used_as(&used_packages, current.package_id(), target, &proc_macros)?;
+            for (to, (from, used_by)) in used {
+                let from_target = match used_by {
+                    UsedBy::Root => Some(target),
+                    UsedBy::Lib => packages[&from].targets().iter().find(|t| t.is_lib()),
+                    UsedBy::CustomBuild => packages[&from]
+                        .targets()
                         .iter()
-                        .cloned()
-                        .chain(
-                            cache[&cache_key][&cur]
-                                .custom_build
-                                .clone()
-                                .unwrap_or_default(),
-                        )
-                    {
-                        if used.insert(dep) {
-                            next.insert(dep);
+                        .find(|t| t.is_custom_build()),
+                }
+                .ok_or_else(|| format_err!("`{}` does not have the target", from))?;
+                let extern_crate_name = extern_crate_names
+                    .get(&from)
+                    .and_then(|names| names.get(from_target))
+                    .and_then(|names| names.get(&to))
+                    .ok_or_else(|| format_err!("`{}` is not a dependency of `{}`", to, from))?
+                    .clone();
+                let edge = LinkedEdge {
+                    from,
+                    target: from_target.description_named(),
+                    extern_crate_name,
+                    from_root: used_by == UsedBy::Root,
+                };
+                outcome.reached_via.insert(to, edge);
+            }
+
+            outcome.unused.trivial = all_ids
+                .iter()
+                .cloned()
+                .filter(|id| {
+                    !outcome.used.contains(id)
+                        && (!all_targets.contains_key(id) || unnecessary_dev_deps.contains(id))
+                })
+                .collect();
+            outcome.unused.maybe_obsolete = all_ids
+                .iter()
+                .cloned()
+                .filter(|id| !(outcome.used.contains(id) || outcome.unused.trivial.contains(id)))
+                .collect();
+
+            let slots = &used_packages[&current.package_id()];
+            let lib_linked = target.is_lib() || outcome.used.contains(&current.package_id());
+            for (to, deps) in resolve.deps(current.package_id()) {
+                for dep in deps {
+                    let used = match dep.kind() {
+                        dependency::Kind::Normal | dependency::Kind::Development => {
+                            slots.get(target).iter().any(|s| s.contains(&to))
+                                || slots.get_unit_test(target).iter().any(|s| s.contains(&to))
+                                || lib_linked && slots.lib.iter().any(|s| s.contains(&to))
                         }
-                    }
+                        dependency::Kind::Build => {
+                            slots.custom_build.iter().any(|s| s.contains(&to))
+                        }
+                    };
+                    let location = locations
+                        .get(&manifest::DependencyKey {
+                            kind: dep.kind(),
+                            platform: dep.platform().cloned(),
+                            name_in_toml: dep.name_in_toml().to_string(),
+                        })
+                        .cloned();
+                    outcome.direct.push(LinkedDirectDependency {
+                        name_in_toml: dep.name_in_toml().to_string(),
+                        package: to,
+                        kind: dep.kind(),
+                        platform: dep.platform().map(ToString::to_string),
+                        manifest_path: current.manifest_path().to_owned(),
+                        line: location.map(|(l, _)| l),
+                        column: location.map(|(_, c)| c),
+                        active: dep.platform().iter().all(|p| match dep.kind() {
+                            dependency::Kind::Build => p.matches(&rustc.host, host_cfg.cfg()),
+                            _ => p.matches(&target_triple, target_cfg.cfg()),
+                        }),
+                        used,
+                    });
+                }
+            }
+            outcome.direct.sort_by_key(|d| (d.line, d.column));
+            let (names, dependency_kinds) = target_edges(
+                &packages,
+                resolve,
+                &extern_crate_names,
+                current.package_id(),
+                target,
+            );
+            outcome.extern_crate_names = names;
+            outcome.dependency_kinds = dependency_kinds;
+
+            for &from in packages.keys() {
+                let slots = used_packages.get(&from);
+                for (to, deps) in resolve.deps(from) {
+                    let used = if from == current.package_id() {
+                        outcome.direct.iter().any(|d| d.package == to && d.used)
+                    } else if deps
+                        .iter()
+                        .all(|d| d.kind() == dependency::Kind::Development)
+                    {
+                        continue;
+                    } else {
+                        outcome.used.contains(&from)
+                            && slots
+                                .iter()
+                                .flat_map(|s| s.lib.iter().chain(&s.custom_build))
+                                .any(|s| s.contains(&to))
+                    };
+                    let edges = outcome.resolved_edges.entry(from).or_default();
+                    edges.insert(to, used);
                 }
-                cur = next;
             }
-            used
-        };
 
-        outcome.unused.trivial = all_ids
-            .iter()
-            .cloned()
-            .filter(|id| {
-                !outcome.used.contains(id)
-                    && (!all_targets.contains_key(id) || unnecessary_dev_deps.contains(id))
-            })
-            .collect();
-        outcome.unused.maybe_obsolete = all_ids
-            .iter()
-            .cloned()
-            .filter(|id| !(outcome.used.contains(id) || outcome.unused.trivial.contains(id)))
-            .collect();
+            outcome.forbidden = outcome
+                .used_as
+                .iter()
+                .filter(|&(&id, &used_as)| used_as == UsedAs::Runtime && policy.forbids(id))
+                .map(|(&id, _)| id)
+                .collect();
+            outcome.ignored = all_ids
+                .iter()
+                .cloned()
+                .filter(|&id| policy.ignores(id))
+                .collect();
+            for id in &outcome.ignored {
+                outcome.used.remove(id);
+                outcome.used_as.remove(id);
+                outcome.unused.trivial.remove(id);
+                outcome.unused.maybe_obsolete.remove(id);
+                outcome.forbidden.remove(id);
+            }
+            outcome.direct.retain(|d| !policy.ignores(d.package));
 
-        Ok(outcome)
+            outcomes.insert(target.clone(), outcome);
+        }
+        Ok(outcomes)
+    }
+
+    /// Checks the `direct` entries.
+    pub fn verdict(&self) -> LinkedVerdict {
+        LinkedVerdict::new(self.direct.iter().cloned())
+    }
+
+    /// Returns the path from the root target to `id`, or `None` if `id` is not used.
+    pub fn why(&self, id: PackageId) -> Option<Vec<(&LinkedEdge, PackageId)>> {
+        let mut path = vec![];
+        let mut to = id;
+        loop {
+            let edge = self.reached_via.get(&to)?;
+            path.push((edge, to));
+            if edge.from_root {
+                break;
+            }
+            to = edge.from;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    fn set_size_bytes(&mut self, size_bytes: &BTreeMap<PackageId, u64>) {
+        self.size_bytes = size_bytes.clone();
+        for targets in self.targets.values_mut() {
+            for linked in targets.values_mut() {
+                linked.set_size_bytes(size_bytes);
+            }
+        }
+    }
+
+    fn with_targets(outcomes: BTreeMap<Target, Self>) -> Self {
+        let mut outcome = Self::union(outcomes.values());
+        for (target, target_outcome) in outcomes {
+            outcome
+                .targets
+                .entry(target_kind_key(target.kind()).to_owned())
+                .or_insert_with(BTreeMap::new)
+                .insert(target.name().to_owned(), target_outcome);
+        }
+        outcome
+    }
+
+    /// Packages are `trivial` in the union only if they are `trivial` for every outcome.
+    fn union<'a>(outcomes: impl IntoIterator<Item = &'a Self>) -> Self {
+        let mut union = Self::default();
+        let mut trivial = None::<BTreeSet<_>>;
+        let mut unused = btreeset!();
+        for outcome in outcomes {
+            union.used.extend(&outcome.used);
+            union.ignored.extend(&outcome.ignored);
+            union.forbidden.extend(&outcome.forbidden);
+            for (&id, &used_as) in &outcome.used_as {
+                let entry = union.used_as.entry(id).or_insert(used_as);
+                *entry = (*entry).min(used_as);
+            }
+            for (&id, edge) in &outcome.reached_via {
+                union.reached_via.entry(id).or_insert_with(|| edge.clone());
+            }
+            for direct in &outcome.direct {
+                match union.direct.iter_mut().find(|d| d.same_entry(direct)) {
+                    Some(d) => {
+                        d.active |= direct.active;
+                        d.used |= direct.used;
+                    }
+                    None => union.direct.push(direct.clone()),
+                }
+            }
+            for (&id, names) in &outcome.extern_crate_names {
+                let union_names = union.extern_crate_names.entry(id).or_default();
+                union_names.extend(names.iter().cloned());
+            }
+            for (&id, kinds) in &outcome.dependency_kinds {
+                let union_kinds = union.dependency_kinds.entry(id).or_default();
+                union_kinds.extend(kinds);
+            }
+            for (&from, edges) in &outcome.resolved_edges {
+                let union_edges = union.resolved_edges.entry(from).or_default();
+                for (&to, &used) in edges {
+                    *union_edges.entry(to).or_default() |= used;
+                }
+            }
+            unused.extend(&outcome.unused.trivial);
+            unused.extend(&outcome.unused.maybe_obsolete);
+            trivial = Some(match trivial {
+                None => outcome.unused.trivial.clone(),
+                Some(trivial) => &trivial & &outcome.unused.trivial,
+            });
+        }
+        union.unused.trivial = trivial.unwrap_or_default();
+        union.unused.maybe_obsolete = unused
+            .into_iter()
+            .filter(|id| !(union.used.contains(id) || union.unused.trivial.contains(id)))
+            .collect();
+        union
     }
 }
 
@@ -645,11 +1989,62 @@ pub struct LinkedPackagesUnused {
     pub maybe_obsolete: BTreeSet<PackageId>,
 }
 
+fn target_kind_key(kind: &TargetKind) -> &'static str {
+    match kind {
+        TargetKind::Lib(_) => "lib",
+        TargetKind::Bin => "bin",
+        TargetKind::Test => "test",
+        TargetKind::Bench => "bench",
+        TargetKind::ExampleLib(_) | TargetKind::ExampleBin => "example",
+        TargetKind::CustomBuild => "custom_build",
+    }
+}
+
+/// Excludes as many of the `candidates` as possible, given that excluding all of them fails.
+///
+/// Instead of trying the candidates one by one, the groups that fail are halved until they
+/// consist of single candidates. Returns the number of the trials.
+fn exclude_by_bisection<'a>(
+    exclude: &mut FixedBitSet,
+    candidates: &'a [usize],
+    mut trial: impl FnMut(&FixedBitSet) -> CargoResult<bool>,
+) -> CargoResult<usize> {
+    for &i in candidates {
+        exclude.set(i, false);
+    }
+    let halve = |groups: &mut Vec<_>, group: &'a [usize]| {
+        if group.len() > 1 {
+            let (left, right) = group.split_at(group.len() / 2);
+            groups.push(right);
+            groups.push(left);
+        }
+    };
+    let mut trials = 0;
+    let mut groups = vec![];
+    halve(&mut groups, candidates);
+    while let Some(group) = groups.pop() {
+        for &i in group {
+            exclude.insert(i);
+        }
+        trials += 1;
+        if !trial(exclude)? {
+            for &i in group {
+                exclude.set(i, false);
+            }
+            halve(&mut groups, group);
+        }
+    }
+    Ok(trials)
+}
+
 #[derive(Debug)]
 struct Exec {
-    target: Target,
     extern_crate_names: HashMap<PackageId, HashMap<Target, HashMap<PackageId, String>>>,
     supports_color: bool,
+    verbose: bool,
+    backend: Backend,
+    host_cfg: Vec<Cfg>,
+    target_cfg: Vec<Cfg>,
     store: Arc<Mutex<ExecStore>>,
 }
 
@@ -659,10 +2054,130 @@ impl Executor for Exec {
         cmd: ProcessBuilder,
         id: PackageId,
         target: &Target,
-        _: CompileMode,
+        mode: CompileMode,
         on_stdout_line: &mut dyn FnMut(&str) -> CargoResult<()>,
         on_stderr_line: &mut dyn FnMut(&str) -> CargoResult<()>,
     ) -> CargoResult<()> {
+        let mut cmd = Rustc::new(cmd, id, target)?;
+        let unused = match self.backend {
+            Backend::Auto | Backend::Lint => {
+                cmd.capture_unused_externs(on_stdout_line, on_stderr_line)?
+            }
+            Backend::Trial => None,
+        };
+        let exclude = match (unused, self.backend) {
+            (Some(unused), _) => unused,
+            (None, Backend::Lint) => {
+                return Err(format_err!(
+                    "Could not get the unused externs of {} from the `unused_crate_dependencies` \
+                     lint",
+                    target.description_named(),
+                ));
+            }
+            (None, _) => {
+                self.exclude_by_trials(&mut cmd, target, on_stdout_line, on_stderr_line)?
+            }
+        };
+
+        let used = cmd
+            .externs()
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| !exclude[i])
+            .map(|(_, e)| e.name())
+            .collect::<HashSet<_>>();
+        let used = self
+            .extern_crate_names
+            .get(&id)
+            .and_then(|extern_crate_names| extern_crate_names.get(target))
+            .expect("`extern_crate_names` should contain all of the targets")
+            .iter()
+            .filter(|(_, name)| used.contains(name.as_str()))
+            .map(|(&id, _)| id);
+
+        let mut store = self.store.lock().unwrap();
+        let slots = store
+            .used_packages
+            .entry(id)
+            .or_insert_with(CacheUsedPackages::default);
+        if is_unit_test(target, mode) {
+            slots.insert_unit_test(target, used);
+        } else {
+            slots.insert(target, used);
+        }
+        Ok(())
+    }
+
+    fn force_rebuild(&self, unit: &Unit) -> bool {
+        let mut store = self.store.lock().unwrap();
+        store
+            .all_targets
+            .entry((*unit).pkg.package_id())
+            .or_insert_with(BTreeSet::new)
+            .insert((*unit).target.clone());
+        store
+            .used_packages
+            .get(&(*unit).pkg.package_id())
+            .map_or(true, |v| {
+                let slot = if is_unit_test(unit.target, unit.mode) {
+                    v.get_unit_test(unit.target)
+                } else {
+                    v.get(unit.target)
+                };
+                slot.is_none()
+            })
+    }
+}
+
+/// Unit tests share their `Target`s with the `lib`s and the `bin`s.
+fn is_unit_test(target: &Target, mode: CompileMode) -> bool {
+    mode.is_any_test() && (target.is_lib() || target.is_bin())
+}
+
+impl Exec {
+    fn eprint_warning(
+        &self,
+        err: &failure::Error,
+        on_stderr_line: &mut dyn FnMut(&str) -> CargoResult<()>,
+    ) -> CargoResult<()> {
+        let mut lines = vec![];
+        for (i, cause) in err.as_fail().iter_chain().enumerate() {
+            let head = if i == 0 && err.as_fail().cause().is_none() {
+                "warning:"
+            } else if i == 0 {
+                "  warning:"
+            } else {
+                "caused by:"
+            };
+            lines.push(if self.supports_color {
+                format!("{} ", Colour::Yellow.bold().paint(head))
+            } else {
+                format!("{} ", head)
+            });
+            for (i, line) in cause.to_string().lines().enumerate() {
+                let mut msg = "".to_owned();
+                if i > 0 {
+                    (0..=head.len()).for_each(|_| msg.push(' '));
+                }
+                msg += line;
+                lines.push(msg);
+            }
+        }
+        for line in lines {
+            on_stderr_line(&line)?;
+        }
+        Ok(())
+    }
+
+    /// Excludes the externs which are not referred in the source code, then puts back the ones
+    /// rustc complains about.
+    fn exclude_by_trials(
+        &self,
+        cmd: &mut Rustc,
+        target: &Target,
+        on_stdout_line: &mut dyn FnMut(&str) -> CargoResult<()>,
+        on_stderr_line: &mut dyn FnMut(&str) -> CargoResult<()>,
+    ) -> CargoResult<FixedBitSet> {
         static E0432_SINGLE_MOD: Lazy<Regex> =
             lazy_regex!(r"\Aunresolved import `([a-zA-Z0-9_]+)`\z");
         static E0433_SINGLE_MOD: Lazy<Regex> =
@@ -670,42 +2185,32 @@ impl Executor for Exec {
         static E0463_SINGLE_MOD: Lazy<Regex> =
             lazy_regex!(r"\Acan't find crate for `([a-zA-Z0-9_]+)`\z");
 
-        let mut cmd = Rustc::new(cmd, id, target)?;
         let mut exclude = FixedBitSet::with_capacity(cmd.externs().len());
-        let uses = crate::parse::find_uses_lossy(
-            target.src_path(),
-            &cmd.externs().iter().map(|e| e.name()).collect(),
-            target.edition(),
-        );
+        // cargo 0.41 does not know the editions after 2018.
+        let edition = match cmd.edition().map(str::parse::<crate::parse::Edition>) {
+            None => target.edition().into(),
+            Some(Ok(edition)) => edition,
+            Some(Err(err)) => {
+                let err = err.context("Falling back to the 2018 edition").into();
+                self.eprint_warning(&err, on_stderr_line)?;
+                crate::parse::Edition::Edition2018
+            }
+        };
+        let source_files = cmd.capture_dep_info(on_stdout_line)?;
+        let uses = cmd.cfgs(&self.host_cfg, &self.target_cfg).and_then(|cfgs| {
+            crate::parse::find_uses_lossy(
+                target.src_path(),
+                source_files.as_deref(),
+                &cmd.externs().iter().map(|e| e.name()).collect(),
+                edition,
+                &cfgs,
+                &|key| cmd.env(key).and_then(|v| v.into_string().ok()),
+            )
+        });
         let uses = match uses {
             Ok(uses) => uses,
             Err(err) => {
-                let mut lines = vec![];
-                for (i, cause) in err.as_fail().iter_chain().enumerate() {
-                    let head = if i == 0 && err.as_fail().cause().is_none() {
-                        "warning:"
-                    } else if i == 0 {
-                        "  warning:"
-                    } else {
-                        "caused by:"
-                    };
-                    lines.push(if self.supports_color {
-                        format!("{} ", Colour::Yellow.bold().paint(head))
-                    } else {
-                        format!("{} ", head)
-                    });
-                    for (i, line) in cause.to_string().lines().enumerate() {
-                        let mut msg = "".to_owned();
-                        if i > 0 {
-                            (0..=head.len()).for_each(|_| msg.push(' '));
-                        }
-                        msg += line;
-                        lines.push(msg);
-                    }
-                }
-                for line in lines {
-                    on_stderr_line(&line)?;
-                }
+                self.eprint_warning(&err, on_stderr_line)?;
                 hashset!()
             }
         };
@@ -715,7 +2220,7 @@ impl Executor for Exec {
             }
         }
 
-        let needs_exclude_one_by_one = loop {
+        let needs_bisection = loop {
             if let Some(errors) =
                 cmd.capture_error_messages(&exclude, on_stdout_line, on_stderr_line)?
             {
@@ -771,62 +2276,27 @@ impl Executor for Exec {
             }
         };
 
-        if needs_exclude_one_by_one {
-            let prev = exclude;
-            exclude = FixedBitSet::with_capacity(cmd.externs().len());
-            let mut success = true;
-            for i in 0..cmd.externs().len() {
-                if prev[i] {
-                    exclude.insert(i);
-                    success = cmd
-                        .capture_error_messages(&exclude, on_stdout_line, on_stderr_line)?
-                        .is_none();
-                    exclude.set(i, success);
-                }
+        if needs_bisection {
+            let candidates = exclude.ones().collect::<Vec<_>>();
+            let mut last_succeeded = false;
+            let trials = exclude_by_bisection(&mut exclude, &candidates, |exclude| {
+                last_succeeded = cmd
+                    .capture_error_messages(exclude, on_stdout_line, on_stderr_line)?
+                    .is_none();
+                Ok(last_succeeded)
+            })?;
+            if self.verbose {
+                on_stderr_line(&format!(
+                    "{} trial compilation(s) for {1} candidate(s), against {1} one by one",
+                    trials,
+                    candidates.len(),
+                ))?;
             }
-            if !success {
-                exclude.set(cmd.externs().len() - 1, false);
+            if !last_succeeded {
                 cmd.run(&exclude, on_stdout_line, on_stderr_line)?;
             }
         }
-
-        let used = cmd
-            .externs()
-            .iter()
-            .enumerate()
-            .filter(|&(i, _)| !exclude[i])
-            .map(|(_, e)| e.name())
-            .collect::<HashSet<_>>();
-        let used = self
-            .extern_crate_names
-            .get(&id)
-            .and_then(|extern_crate_names| extern_crate_names.get(target))
-            .expect("`extern_crate_names` should contain all of the targets")
-            .iter()
-            .filter(|(_, name)| used.contains(name.as_str()))
-            .map(|(&id, _)| id);
-
-        self.store
-            .lock()
-            .unwrap()
-            .used_packages
-            .entry(id)
-            .or_insert_with(CacheUsedPackages::default)
-            .insert(target, used);
-        Ok(())
-    }
-
-    fn force_rebuild(&self, unit: &Unit) -> bool {
-        let mut store = self.store.lock().unwrap();
-        store
-            .all_targets
-            .entry((*unit).pkg.package_id())
-            .or_insert_with(BTreeSet::new)
-            .insert((*unit).target.clone());
-        store
-            .used_packages
-            .get(&(*unit).pkg.package_id())
-            .map_or(true, |v| v.get(&(*unit).target).is_none())
+        Ok(exclude)
     }
 }
 
@@ -844,3 +2314,416 @@ impl ExecStore {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{id, outcome_for, PLATFORM};
+    use cargo::core::compiler::{CompileKind, CompileTarget, ProfileKind};
+    use cargo::core::manifest::{LibKind, Target};
+
+    use cargo::core::{Edition, PackageId, PackageIdSpec};
+    use cargo::CargoResult;
+    use fixedbitset::FixedBitSet;
+    use maplit::{btreemap, btreeset, hashmap, hashset};
+    use structopt::StructOpt as _;
+
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn used_transitively(target: &Target) -> CargoResult<BTreeSet<PackageId>> {
+        super::used_transitively(&used_packages(), id("root"), target)
+            .map(|used| used.keys().cloned().collect())
+    }
+
+    /// `root` uses a different package from each target and `dep` from all but `lib`. `dep` uses
+    /// `dep_lib` from its `lib` and `dep_build` from its build script.
+    fn used_packages() -> BTreeMap<PackageId, super::CacheUsedPackages> {
+        let root = super::CacheUsedPackages {
+            lib: Some(btreeset!(id("lib"))),
+            bin: btreemap!("root".to_owned() => btreeset!(id("bin"), id("dep"))),
+            test: btreemap!("root-test".to_owned() => btreeset!(id("test"), id("dep"))),
+            bench: btreemap!("root-bench".to_owned() => btreeset!(id("bench"), id("dep"))),
+            example_bin: btreemap!("root-example".to_owned() => btreeset!(id("example"), id("dep"))),
+            ..Default::default()
+        };
+        let dep = super::CacheUsedPackages {
+            lib: Some(btreeset!(id("dep_lib"))),
+            custom_build: Some(btreeset!(id("dep_build"))),
+            ..Default::default()
+        };
+        let mut used_packages = btreemap!(id("root") => root, id("dep") => dep);
+        for name in &[
+            "lib",
+            "bin",
+            "test",
+            "bench",
+            "example",
+            "dep_lib",
+            "dep_build",
+        ] {
+            let leaf = super::CacheUsedPackages {
+                lib: Some(btreeset!()),
+                ..Default::default()
+            };
+            used_packages.insert(id(name), leaf);
+        }
+        used_packages
+    }
+
+    #[test]
+    fn test_used_transitively_reached_from() -> CargoResult<()> {
+        let src_path = "/src/main.rs".into();
+        let target = Target::bin_target("root", src_path, None, Edition::Edition2018);
+        let used = super::used_transitively(&used_packages(), id("root"), &target)?;
+        assert_eq!(used[&id("dep")], (id("root"), super::UsedBy::Root));
+        assert_eq!(used[&id("dep_lib")], (id("dep"), super::UsedBy::Lib));
+        assert_eq!(
+            used[&id("dep_build")],
+            (id("dep"), super::UsedBy::CustomBuild)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_used_transitively_lib() -> CargoResult<()> {
+        let src_path = "/src/lib.rs".into();
+        let target = Target::lib_target("root", vec![LibKind::Lib], src_path, Edition::Edition2018);
+        assert_eq!(used_transitively(&target)?, btreeset!(id("lib")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_used_transitively_bin() -> CargoResult<()> {
+        let src_path = "/src/main.rs".into();
+        let target = Target::bin_target("root", src_path, None, Edition::Edition2018);
+        let expected = btreeset!(id("bin"), id("dep"), id("dep_lib"), id("dep_build"));
+        assert_eq!(used_transitively(&target)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_used_transitively_test() -> CargoResult<()> {
+        let src_path = "/tests/root-test.rs".into();
+        let target = Target::test_target("root-test", src_path, None, Edition::Edition2018);
+        let expected = btreeset!(id("test"), id("dep"), id("dep_lib"), id("dep_build"));
+        assert_eq!(used_transitively(&target)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_used_transitively_bench() -> CargoResult<()> {
+        let src_path = "/benches/root-bench.rs".into();
+        let target = Target::bench_target("root-bench", src_path, None, Edition::Edition2018);
+        let expected = btreeset!(id("bench"), id("dep"), id("dep_lib"), id("dep_build"));
+        assert_eq!(used_transitively(&target)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_used_transitively_example() -> CargoResult<()> {
+        let src_path = "/examples/root-example.rs".into();
+        let target =
+            Target::example_target("root-example", vec![], src_path, None, Edition::Edition2018);
+        let expected = btreeset!(id("example"), id("dep"), id("dep_lib"), id("dep_build"));
+        assert_eq!(used_transitively(&target)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_used_transitively_not_cached() {
+        let src_path = "/src/bin/nonexistent.rs".into();
+        let target = Target::bin_target("nonexistent", src_path, None, Edition::Edition2018);
+        assert!(used_transitively(&target).is_err());
+    }
+
+    #[test]
+    fn test_used_as() -> CargoResult<()> {
+        use super::UsedAs::{Build, ProcMacro, Runtime};
+
+        let src_path = "/src/main.rs".into();
+        let target = Target::bin_target("root", src_path, None, Edition::Edition2018);
+        let proc_macros = hashset!(id("dep_lib"));
+        let used_as = super::used_as(&used_packages(), id("root"), &target, &proc_macros)?;
+        let expected = btreemap!(
+            id("bin") => Runtime,
+            id("dep") => Runtime,
+            id("dep_lib") => ProcMacro,
+            id("dep_build") => Build,
+        );
+        assert_eq!(used_as, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_always_used() -> CargoResult<()> {
+        let src_path = "/src/main.rs".into();
+        let target = Target::bin_target("root", src_path, None, Edition::Edition2018);
+        let extern_crate_names = hashmap!(
+            id("root") => hashmap!(
+                target.clone() => hashmap!(
+                    id("dep") => "dep".to_owned(),
+                    id("probe") => "probe".to_owned(),
+                    id("unused") => "unused".to_owned(),
+                ),
+            ),
+        );
+        let policy = super::Policy {
+            always_used: vec![PackageIdSpec::parse("probe")?],
+            ..Default::default()
+        };
+        let used_packages = policy.with_always_used(&used_packages(), &extern_crate_names);
+        let expected = btreeset!(id("bin"), id("dep"), id("probe"));
+        assert_eq!(used_packages[&id("root")].get(&target), Some(&expected));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_by_bisection() -> CargoResult<()> {
+        let needed = [3, 40];
+        let mut exclude = FixedBitSet::with_capacity(70);
+        exclude.insert_range(..64);
+        let candidates = exclude.ones().collect::<Vec<_>>();
+        let trials = super::exclude_by_bisection(&mut exclude, &candidates, |exclude| {
+            Ok(needed.iter().all(|&i| !exclude[i]))
+        })?;
+        let expected = (0..64).filter(|i| !needed.contains(i)).collect::<Vec<_>>();
+        assert_eq!(exclude.ones().collect::<Vec<_>>(), expected);
+        assert!(trials < candidates.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verdict() {
+        use cargo::core::dependency::Kind::{Development, Normal};
+
+        let direct = |name: &str, kind, active, used| super::LinkedDirectDependency {
+            name_in_toml: name.to_owned(),
+            package: id(name),
+            kind,
+            platform: None,
+            manifest_path: "/root/Cargo.toml".into(),
+            line: None,
+            column: None,
+            active,
+            used,
+        };
+        let workspace = |direct| {
+            let linked = super::LinkedPackages {
+                direct,
+                ..Default::default()
+            };
+            super::LinkedWorkspace::new(btreemap!(id("root") => linked))
+        };
+
+        // `nix` is used on one of the platforms, and `winapi` is inactive on both.
+        let outcome = super::LinkedOutcome {
+            platforms: btreemap!(
+                PLATFORM.to_owned() => workspace(vec![
+                    direct("nix", Normal, true, true),
+                    direct("winapi", Normal, false, false),
+                    direct("tempdir", Development, true, false),
+                ]),
+                "x86_64-apple-darwin".to_owned() => workspace(vec![
+                    direct("nix", Normal, true, false),
+                    direct("winapi", Normal, false, false),
+                    direct("tempdir", Development, true, false),
+                ]),
+            ),
+            ..Default::default()
+        };
+        assert!(outcome.verdict().is_clean());
+
+        let linked = &outcome.platforms["x86_64-apple-darwin"].members[&id("root")];
+        match linked.verdict() {
+            super::LinkedVerdict::Unused(unused) => {
+                let unused = unused.iter().map(|d| d.package).collect::<Vec<_>>();
+                assert_eq!(unused, [id("nix")]);
+            }
+            super::LinkedVerdict::Clean => panic!("`nix` should be unused"),
+        }
+    }
+
+    #[test]
+    fn test_fix() -> CargoResult<()> {
+        use cargo::core::dependency::Kind::{Development, Normal};
+
+        // The `lib` has the default `test = true`, and `log` is used only by its unit tests. The
+        // optional `serde` is enabled by the other member.
+        let tempdir = tempdir::TempDir::new("cargo-linked-test-fix")?;
+        let manifest_path = tempdir.path().join("Cargo.toml");
+        crate::fs::write(
+            &manifest_path,
+            r#"[package]
+name = "root"
+version = "0.0.0"
+edition = "2018"
+
+[workspace]
+members = ["b"]
+
+[dependencies]
+log = "0.4"
+nix = "0.17"
+serde = { version = "1", optional = true }
+
+[dev-dependencies]
+tempdir = "0.3"
+"#,
+        )?;
+        std::fs::create_dir(tempdir.path().join("src"))?;
+        crate::fs::write(tempdir.path().join("src").join("lib.rs"), "")?;
+        std::fs::create_dir_all(tempdir.path().join("b").join("src"))?;
+        crate::fs::write(
+            tempdir.path().join("b").join("Cargo.toml"),
+            r#"[package]
+name = "b"
+version = "0.0.0"
+edition = "2018"
+
+[dependencies]
+root = { path = ".." }
+
+[features]
+serde = ["root/serde"]
+"#,
+        )?;
+        crate::fs::write(tempdir.path().join("b").join("src").join("lib.rs"), "")?;
+
+        let direct = |name: &str, kind, used| super::LinkedDirectDependency {
+            name_in_toml: name.to_owned(),
+            package: id(name),
+            kind,
+            platform: None,
+            manifest_path: manifest_path.clone(),
+            line: None,
+            column: None,
+            active: true,
+            used,
+        };
+        let linked = super::LinkedPackages {
+            direct: vec![
+                direct("log", Normal, true),
+                direct("nix", Normal, false),
+                direct("serde", Normal, false),
+                direct("tempdir", Development, false),
+            ],
+            ..Default::default()
+        };
+        let outcome = outcome_for(btreemap!(id("root") => linked));
+
+        // `--fix` tries the entries which `--deny unused` rejects, and keeps `serde`.
+        match outcome.verdict() {
+            super::LinkedVerdict::Unused(unused) => {
+                let unused = unused.iter().map(|d| d.package).collect::<Vec<_>>();
+                assert_eq!(unused, [id("nix"), id("serde")]);
+            }
+            super::LinkedVerdict::Clean => panic!("`nix` and `serde` should be unused"),
+        }
+        let mut diff = vec![];
+        super::fix(&outcome, true, &cargo::Config::default()?, &mut diff)?;
+        let diff = String::from_utf8(diff)?;
+        let removed = diff
+            .lines()
+            .filter(|l| l.starts_with('-') && !l.starts_with("---"))
+            .collect::<Vec<_>>();
+        assert_eq!(removed, [r#"-nix = "0.17""#]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_targets() {
+        let lib = Target::lib_target(
+            "root",
+            vec![LibKind::Lib],
+            "/src/lib.rs".into(),
+            Edition::Edition2018,
+        );
+        let bin = Target::bin_target("root", "/src/main.rs".into(), None, Edition::Edition2018);
+        let test = Target::test_target("t", "/tests/t.rs".into(), None, Edition::Edition2018);
+        let outcome = |used: BTreeSet<PackageId>, trivial| super::LinkedPackages {
+            used,
+            unused: super::LinkedPackagesUnused {
+                trivial,
+                maybe_obsolete: btreeset!(),
+            },
+            ..Default::default()
+        };
+        let outcomes = btreemap!(
+            lib => outcome(btreeset!(id("lib")), btreeset!(id("winapi"), id("test"))),
+            bin => outcome(btreeset!(id("lib"), id("bin")), btreeset!(id("winapi"), id("test"))),
+            test => outcome(btreeset!(id("lib"), id("test")), btreeset!(id("winapi"))),
+        );
+        let linked = super::LinkedPackages::with_targets(outcomes);
+
+        assert_eq!(linked.used, btreeset!(id("lib"), id("bin"), id("test")));
+        assert_eq!(linked.unused.trivial, btreeset!(id("winapi")));
+        let keys = linked
+            .targets
+            .iter()
+            .flat_map(|(kind, t)| t.keys().map(move |name| (&**kind, &**name)))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, [("bin", "root"), ("lib", "root"), ("test", "t")]);
+        assert_eq!(
+            linked.targets["test"]["t"].used,
+            btreeset!(id("lib"), id("test"))
+        );
+    }
+
+    #[test]
+    fn test_requested_triples() {
+        let host = "x86_64-unknown-linux-gnu";
+        assert_eq!(super::requested_triples(&[], host), [(None, host)]);
+        let triples = &[
+            "wasm32-unknown-unknown".to_owned(),
+            "aarch64-linux-android".to_owned(),
+            "wasm32-unknown-unknown".to_owned(),
+        ];
+        assert_eq!(
+            super::requested_triples(triples, host),
+            [
+                (Some("wasm32-unknown-unknown"), "wasm32-unknown-unknown"),
+                (Some("aarch64-linux-android"), "aarch64-linux-android"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_cache_key() -> CargoResult<()> {
+        let dev = ProfileKind::Dev;
+        let host = super::CacheKey::new(&dev, CompileKind::Host);
+        assert_eq!(host.target, None);
+        let wasm32 = CompileKind::Target(CompileTarget::new("wasm32-unknown-unknown")?);
+        let wasm32 = super::CacheKey::new(&dev, wasm32);
+        assert_eq!(wasm32.target.as_deref(), Some("wasm32-unknown-unknown"));
+        assert_eq!(wasm32.profile_kind, host.profile_kind);
+        assert_ne!(wasm32, host);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_conflicts() {
+        let parse = |args: &[&str]| {
+            let args = ["cargo", "linked"].iter().chain(args);
+            super::Cargo::from_iter_safe(args).map(|super::Cargo::Linked(opt)| opt)
+        };
+        assert!(parse(&["--all-targets", "--lib"]).is_err());
+        assert!(parse(&["--all-targets", "--bin", "foo"]).is_err());
+        assert!(parse(&["--exclude", "foo"]).is_err());
+        assert!(parse(&["--deny", "unused", "--lib"]).is_err());
+        assert!(parse(&["--policy", "policy.toml", "--bin", "foo"]).is_err());
+        assert!(parse(&["--policy", "policy.toml", "--deny", "unused"]).is_ok());
+        assert!(parse(&["--artifact", "foo", "--deny", "unused"]).is_err());
+        assert!(parse(&["--artifact", "foo", "--policy", "policy.toml"]).is_err());
+        assert!(parse(&["--artifact", "foo", "--fix"]).is_err());
+        let opt = parse(&[
+            "--workspace",
+            "--exclude",
+            "foo",
+            "--target",
+            "wasm32-unknown-unknown",
+        ])
+        .unwrap();
+        assert!(opt.workspace);
+        assert_eq!(opt.exclude, ["foo"]);
+        assert_eq!(opt.target, ["wasm32-unknown-unknown"]);
+    }
+}
@@ -0,0 +1,291 @@
+//! The licenses of the packages linked into the artifacts.
+//!
+//! The members themselves are left out.
+
+use crate::fs;
+use crate::{LinkedOutcome, LinkedPackagesMetadata};
+
+use ansi_term::{Colour, Style};
+use cargo::core::PackageId;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// The prefixes of the names of the files bundled into the notices, in uppercase.
+const LICENSE_FILE_PREFIXES: &[&str] = &[
+    "COPYING",
+    "COPYRIGHT",
+    "LICENCE",
+    "LICENSE",
+    "NOTICE",
+    "UNLICENSE",
+];
+
+/// The `license` field as an SPDX expression. The legacy `/` is read as `OR`.
+pub(crate) fn expression(license: &str) -> String {
+    license
+        .split('/')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+/// Groups the linked packages by their license expressions, followed by the ones with only
+/// `license-file`s and the ones with neither.
+pub(crate) fn report(outcome: &LinkedOutcome, color: bool) -> String {
+    let paint = |style: Style, s: &str| {
+        if color {
+            style.paint(s).to_string()
+        } else {
+            s.to_owned()
+        }
+    };
+    let licenses = Licenses::new(outcome);
+
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+    let mut license_file_only = vec![];
+    let mut missing = vec![];
+    for &id in &licenses.packages {
+        if let Some(license) = licenses.metadata.licenses.get(&id) {
+            groups.entry(expression(license)).or_default().push(id);
+        } else if let Some(license_file) = licenses.metadata.license_files.get(&id) {
+            license_file_only.push((id, license_file));
+        } else {
+            missing.push(id);
+        }
+    }
+
+    let mut ret = "".to_owned();
+    for (expression, ids) in &groups {
+        let header = format!("{} ({})", expression, ids.len());
+        ret += &format!("{}\n", paint(Style::new().bold(), &header));
+        for &id in ids {
+            ret += &format!("    {}\n", package(id));
+        }
+    }
+    if !license_file_only.is_empty() {
+        let header = format!("license-file only ({})", license_file_only.len());
+        ret += &format!("{}\n", paint(Colour::Yellow.bold(), &header));
+        for (id, license_file) in license_file_only {
+            ret += &format!(
+                "    {} {}: {}\n",
+                paint(Colour::Yellow.normal(), "!"),
+                package(id),
+                license_file.display(),
+            );
+        }
+    }
+    if !missing.is_empty() {
+        let header = format!("missing ({})", missing.len());
+        ret += &format!("{}\n", paint(Colour::Red.bold(), &header));
+        for id in missing {
+            ret += &format!("    {} {}\n", paint(Colour::Red.normal(), "✗"), package(id));
+        }
+    }
+    ret
+}
+
+/// Bundles the license texts in the directories of the linked packages.
+///
+/// The `license-file`s and the files named like `LICENSE*`, `COPYING*` or `NOTICE*` are
+/// included.
+pub(crate) fn notices(outcome: &LinkedOutcome) -> failure::Fallible<String> {
+    let licenses = Licenses::new(outcome);
+    let rule = "=".repeat(80);
+
+    let mut ret =
+        "THIRD-PARTY NOTICES\n\nThis software includes the following packages.\n".to_owned();
+    for &id in &licenses.packages {
+        ret += &format!("\n{}\n{}\n", rule, package(id));
+        let metadata = licenses.metadata;
+        let license = match (metadata.licenses.get(&id), metadata.license_files.get(&id)) {
+            (Some(license), _) => expression(license),
+            (None, Some(_)) => "see the license text".to_owned(),
+            (None, None) => "unknown".to_owned(),
+        };
+        ret += &format!("License: {}\n{}\n", license, rule);
+
+        let root = metadata.package_roots.get(&id).map(|p| &**p);
+        let texts = texts(root, metadata.license_files.get(&id).map(|p| &**p))?;
+        if texts.is_empty() {
+            ret += "\nNo license text was found.\n";
+        }
+        for path in texts {
+            let name = root
+                .and_then(|r| path.strip_prefix(r).ok())
+                .unwrap_or(&path);
+            // Some license files are in Latin-1.
+            let text = String::from_utf8_lossy(&fs::read(&path)?).into_owned();
+            ret += &format!("\n---- {} ----\n\n{}", name.display(), text);
+            if !text.ends_with('\n') {
+                ret += "\n";
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// The license files of a package, sorted.
+fn texts(root: Option<&Path>, license_file: Option<&Path>) -> failure::Fallible<Vec<PathBuf>> {
+    let mut texts = BTreeSet::new();
+    texts.extend(license_file.filter(|p| p.is_file()).map(ToOwned::to_owned));
+    if let Some(root) = root {
+        for entry in fs::read_dir(root)? {
+            let name = entry.file_name().to_string_lossy().to_uppercase();
+            if LICENSE_FILE_PREFIXES.iter().any(|p| name.starts_with(p)) && entry.path().is_file() {
+                texts.insert(entry.path());
+            }
+        }
+    }
+    Ok(texts.into_iter().collect())
+}
+
+/// The linked packages merged across the platforms, and their license fields.
+struct Licenses<'a> {
+    packages: BTreeSet<PackageId>,
+    metadata: &'a LinkedPackagesMetadata,
+}
+
+impl<'a> Licenses<'a> {
+    fn new(outcome: &'a LinkedOutcome) -> Self {
+        let mut packages = outcome.runtime();
+        for workspace in outcome.platforms.values() {
+            for member in workspace.members.keys() {
+                packages.remove(member);
+            }
+        }
+        Self {
+            packages,
+            metadata: &outcome.metadata,
+        }
+    }
+}
+
+fn package(id: PackageId) -> String {
+    format!("{} v{}", id.name(), id.version())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{id, outcome_for};
+    use crate::{LinkedOutcome, LinkedPackages, LinkedPackagesMetadata, UsedAs};
+
+    use failure::Fallible;
+    use maplit::{btreemap, btreeset};
+    use tempdir::TempDir;
+
+    use std::path::Path;
+
+    /// `a` and `b` are dual-licensed, `c` has only a `license-file`, `d` has nothing, and `e` is a
+    /// proc-macro.
+    fn outcome(root: &Path) -> LinkedOutcome {
+        let linked = LinkedPackages {
+            used: btreeset!(id("a"), id("b"), id("c"), id("d"), id("e")),
+            used_as: btreemap!(id("e") => UsedAs::ProcMacro),
+            ..Default::default()
+        };
+        let mut outcome = outcome_for(btreemap!(id("root") => linked));
+        outcome.metadata = LinkedPackagesMetadata {
+            licenses: btreemap!(
+                id("a") => "MIT OR Apache-2.0".to_owned(),
+                id("b") => "MIT/Apache-2.0".to_owned(),
+                id("e") => "MIT".to_owned(),
+            ),
+            license_files: btreemap!(id("c") => root.join("c").join("LICENSE.txt")),
+            package_roots: btreemap!(
+                id("a") => root.join("a"),
+                id("c") => root.join("c"),
+                id("d") => root.join("d"),
+            ),
+            ..Default::default()
+        };
+        outcome
+    }
+
+    #[test]
+    fn test_report() {
+        assert_eq!(
+            super::report(&outcome(Path::new("/src")), false),
+            r#"MIT OR Apache-2.0 (2)
+    a v0.0.0
+    b v0.0.0
+license-file only (1)
+    ! c v0.0.0: /src/c/LICENSE.txt
+missing (1)
+    ✗ d v0.0.0
+"#,
+        );
+    }
+
+    #[test]
+    fn test_notices() -> Fallible<()> {
+        let tempdir = TempDir::new("cargo-linked-test-license")?;
+        for (path, text) in &[
+            ("a/LICENSE-MIT", "MIT License\n"),
+            ("a/LICENSE-APACHE", "Apache License"),
+            ("a/README.md", "# a\n"),
+            ("c/LICENSE.txt", "All rights reserved.\n"),
+        ] {
+            let path = tempdir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, text)?;
+        }
+        // Not UTF-8
+        std::fs::write(tempdir.path().join("c").join("NOTICE"), b"Jos\xe9\n")?;
+        std::fs::create_dir(tempdir.path().join("d"))?;
+
+        let rule = "=".repeat(80);
+        assert_eq!(
+            super::notices(&outcome(tempdir.path()))?,
+            format!(
+                r#"THIRD-PARTY NOTICES
+
+This software includes the following packages.
+
+{rule}
+a v0.0.0
+License: MIT OR Apache-2.0
+{rule}
+
+---- LICENSE-APACHE ----
+
+Apache License
+
+---- LICENSE-MIT ----
+
+MIT License
+
+{rule}
+b v0.0.0
+License: MIT OR Apache-2.0
+{rule}
+
+No license text was found.
+
+{rule}
+c v0.0.0
+License: see the license text
+{rule}
+
+---- LICENSE.txt ----
+
+All rights reserved.
+
+---- NOTICE ----
+
+Jos{replacement}
+
+{rule}
+d v0.0.0
+License: unknown
+{rule}
+
+No license text was found.
+"#,
+                rule = rule,
+                replacement = std::char::REPLACEMENT_CHARACTER,
+            ),
+        );
+        Ok(())
+    }
+}
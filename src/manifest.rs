@@ -0,0 +1,473 @@
+use cargo::core::dependency;
+use cargo_platform::Platform;
+use failure::{Fallible, ResultExt as _};
+use maplit::hashmap;
+use toml_edit::{DocumentMut, ImDocument, Item, TableLike};
+
+use std::collections::HashMap;
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub(crate) struct DependencyKey {
+    pub(crate) kind: dependency::Kind,
+    pub(crate) platform: Option<Platform>,
+    pub(crate) name_in_toml: String,
+}
+
+/// Finds the 1-based line and column of each key in the dependency tables.
+pub(crate) fn locate_dependencies(
+    manifest: &str,
+) -> Fallible<HashMap<DependencyKey, (usize, usize)>> {
+    let doc = ImDocument::parse(manifest)
+        .with_context(|_| failure::err_msg("Failed to parse the manifest"))?;
+    let mut locations = hashmap!();
+
+    let mut locate = |table: &dyn TableLike, platform: Option<&Platform>| {
+        for &(kind, keys) in &[
+            (dependency::Kind::Normal, &["dependencies"][..]),
+            (
+                dependency::Kind::Development,
+                &["dev-dependencies", "dev_dependencies"],
+            ),
+            (
+                dependency::Kind::Build,
+                &["build-dependencies", "build_dependencies"],
+            ),
+        ] {
+            for deps in keys
+                .iter()
+                .flat_map(|&k| table.get(k))
+                .flat_map(Item::as_table_like)
+            {
+                for (name_in_toml, _) in deps.iter() {
+                    let span = deps.key(name_in_toml).and_then(|k| k.span());
+                    if let Some(span) = span {
+                        let key = DependencyKey {
+                            kind,
+                            platform: platform.cloned(),
+                            name_in_toml: name_in_toml.to_owned(),
+                        };
+                        locations.insert(key, line_column(manifest, span.start));
+                    }
+                }
+            }
+        }
+    };
+
+    locate(doc.as_table(), None);
+    if let Some(targets) = doc.get("target").and_then(Item::as_table_like) {
+        for (platform, table) in targets.iter() {
+            if let (Ok(platform), Some(table)) = (platform.parse(), table.as_table_like()) {
+                locate(table, Some(&platform));
+            }
+        }
+    }
+    Ok(locations)
+}
+
+/// Removes the entries, keeping the formatting and the comments of the rest.
+pub(crate) fn remove_dependencies(manifest: &str, keys: &[DependencyKey]) -> Fallible<String> {
+    let mut doc = manifest
+        .parse::<DocumentMut>()
+        .with_context(|_| failure::err_msg("Failed to parse the manifest"))?;
+
+    for key in keys {
+        let target_key = match &key.platform {
+            None => None,
+            Some(platform) => doc
+                .get("target")
+                .and_then(Item::as_table_like)
+                .and_then(|targets| {
+                    targets
+                        .iter()
+                        .find(|(k, _)| k.parse().ok().as_ref() == Some(platform))
+                })
+                .map(|(k, _)| k.to_owned()),
+        };
+        let table = match &target_key {
+            None if key.platform.is_some() => None,
+            None => Some(doc.as_table_mut() as &mut dyn TableLike),
+            Some(target_key) => doc
+                .get_mut("target")
+                .and_then(Item::as_table_like_mut)
+                .and_then(|targets| targets.get_mut(target_key))
+                .and_then(Item::as_table_like_mut),
+        };
+        let table_names = match key.kind {
+            dependency::Kind::Normal => &["dependencies"][..],
+            dependency::Kind::Development => &["dev-dependencies", "dev_dependencies"],
+            dependency::Kind::Build => &["build-dependencies", "build_dependencies"],
+        };
+        let removed = table.into_iter().any(|table| {
+            table
+                .iter_mut()
+                .filter(|(k, _)| table_names.contains(&k.get()))
+                .flat_map(|(_, deps)| deps.as_table_like_mut())
+                .any(|deps| deps.remove(&key.name_in_toml).is_some())
+        });
+        if !removed {
+            return Err(failure::err_msg(format!(
+                "Could not find `{}` in the manifest",
+                key.name_in_toml,
+            )));
+        }
+        if let Some(target_key) = target_key {
+            remove_emptied_target_tables(&mut doc, &target_key, table_names);
+        }
+    }
+    Ok(doc.to_string())
+}
+
+/// Removes `[target.<target_key>.<table_names>]` if it has got empty, and then
+/// `[target.<target_key>]` and `[target]` likewise.
+fn remove_emptied_target_tables(doc: &mut DocumentMut, target_key: &str, table_names: &[&str]) {
+    let is_empty =
+        |item: Option<&Item>| item.and_then(Item::as_table_like).map(TableLike::is_empty);
+    let targets = match doc.get_mut("target").and_then(Item::as_table_like_mut) {
+        None => return,
+        Some(targets) => targets,
+    };
+    if let Some(target) = targets
+        .get_mut(target_key)
+        .and_then(Item::as_table_like_mut)
+    {
+        for &table_name in table_names {
+            if is_empty(target.get(table_name)) == Some(true) {
+                target.remove(table_name);
+            }
+        }
+    }
+    if is_empty(targets.get(target_key)) == Some(true) {
+        targets.remove(target_key);
+    }
+    if is_empty(doc.get("target")) == Some(true) {
+        doc.remove("target");
+    }
+}
+
+/// Returns the `[features]` which refer to the dependency.
+pub(crate) fn features_referring_to(manifest: &str, name_in_toml: &str) -> Fallible<Vec<String>> {
+    let doc = ImDocument::parse(manifest)
+        .with_context(|_| failure::err_msg("Failed to parse the manifest"))?;
+    let features = match doc.get("features").and_then(Item::as_table_like) {
+        None => return Ok(vec![]),
+        Some(features) => features,
+    };
+    let refers = |value: &str| {
+        let value = value.trim_start_matches("dep:");
+        value == name_in_toml
+            || value.starts_with(&format!("{}/", name_in_toml))
+            || value.starts_with(&format!("{}?/", name_in_toml))
+    };
+    Ok(features
+        .iter()
+        .filter(|(_, values)| {
+            values
+                .as_array()
+                .into_iter()
+                .flatten()
+                .flat_map(|v| v.as_str())
+                .any(refers)
+        })
+        .map(|(feature, _)| feature.to_owned())
+        .collect())
+}
+
+/// Returns the places which enable `feature` of `package`, i.e. the `features` of the dependency
+/// entries on `package` and the `<dependency>/<feature>` values of `[features]`.
+pub(crate) fn features_enabling(
+    manifest: &str,
+    package: &str,
+    feature: &str,
+) -> Fallible<Vec<String>> {
+    let doc = ImDocument::parse(manifest)
+        .with_context(|_| failure::err_msg("Failed to parse the manifest"))?;
+    let (mut places, mut names_in_toml) = (vec![], vec![]);
+
+    let mut find = |table: &dyn TableLike, prefix: &str| {
+        for &table_name in &[
+            "dependencies",
+            "dev-dependencies",
+            "dev_dependencies",
+            "build-dependencies",
+            "build_dependencies",
+        ] {
+            let deps = table.get(table_name).and_then(Item::as_table_like);
+            for (name_in_toml, dep) in deps.into_iter().flat_map(TableLike::iter) {
+                let dep_package = dep.get("package").and_then(Item::as_str);
+                if dep_package.unwrap_or(name_in_toml) != package {
+                    continue;
+                }
+                names_in_toml.push(name_in_toml.to_owned());
+                let enables = dep
+                    .get("features")
+                    .and_then(Item::as_array)
+                    .into_iter()
+                    .flatten()
+                    .any(|v| v.as_str() == Some(feature));
+                if enables {
+                    places.push(format!("{}{}.{}", prefix, table_name, name_in_toml));
+                }
+            }
+        }
+    };
+
+    find(doc.as_table(), "");
+    if let Some(targets) = doc.get("target").and_then(Item::as_table_like) {
+        for (platform, table) in targets.iter() {
+            if let Some(table) = table.as_table_like() {
+                find(table, &format!("target.{:?}.", platform));
+            }
+        }
+    }
+
+    let features = doc.get("features").and_then(Item::as_table_like);
+    for (name, values) in features.into_iter().flat_map(TableLike::iter) {
+        let enables = values
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|v| v.as_str())
+            .any(|v| {
+                names_in_toml.iter().any(|n| {
+                    v == format!("{}/{}", n, feature) || v == format!("{}?/{}", n, feature)
+                })
+            });
+        if enables {
+            places.push(format!("features.{}", name));
+        }
+    }
+    Ok(places)
+}
+
+/// The entries of `[package.metadata.cargo-linked]` or `[workspace.metadata.cargo-linked]`.
+#[derive(Default, PartialEq, Debug)]
+pub(crate) struct LinkedMetadata {
+    pub(crate) always_used: Vec<String>,
+    pub(crate) ignore: Vec<String>,
+    pub(crate) never: Vec<String>,
+}
+
+/// Reads `[<parent>.metadata.cargo-linked]`.
+pub(crate) fn linked_metadata(manifest: &str, parent: &str) -> Fallible<LinkedMetadata> {
+    let doc = ImDocument::parse(manifest)
+        .with_context(|_| failure::err_msg("Failed to parse the manifest"))?;
+    let mut metadata = LinkedMetadata::default();
+    let table = doc
+        .get(parent)
+        .and_then(|t| t.get("metadata"))
+        .and_then(|t| t.get("cargo-linked"))
+        .and_then(Item::as_table_like);
+    for (key, value) in table.into_iter().flat_map(TableLike::iter) {
+        let field = match key {
+            "always-used" => &mut metadata.always_used,
+            "ignore" => &mut metadata.ignore,
+            "never" => &mut metadata.never,
+            key => {
+                return Err(failure::err_msg(format!(
+                    "Unknown key in `[{}.metadata.cargo-linked]`: `{}`",
+                    parent, key,
+                )));
+            }
+        };
+        let values = value
+            .as_array()
+            .and_then(|a| {
+                a.iter()
+                    .map(|v| v.as_str().map(ToOwned::to_owned))
+                    .collect()
+            })
+            .ok_or_else(|| {
+                failure::err_msg(format!(
+                    "`{}.metadata.cargo-linked.{}` must be an array of strings",
+                    parent, key,
+                ))
+            })?;
+        *field = values;
+    }
+    Ok(metadata)
+}
+
+fn line_column(text: &str, pos: usize) -> (usize, usize) {
+    let before = &text[..pos];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DependencyKey;
+
+    use cargo::core::dependency;
+    use failure::Fallible;
+
+    #[test]
+    fn test_locate_dependencies() -> Fallible<()> {
+        static MANIFEST: &str = r#"[package]
+name = "foo"
+version = "0.0.0"
+
+[dependencies]
+serde = "1"
+serde_json = { version = "1" }
+
+[dependencies.regex]
+version = "1"
+
+[dev-dependencies]
+maplit = "1"
+
+[target.'cfg(unix)'.build-dependencies]
+cc = "1"
+"#;
+
+        let locations = super::locate_dependencies(MANIFEST)?;
+        let location = |kind, platform: Option<&str>, name_in_toml: &str| {
+            let platform = platform.map(|p| p.parse().unwrap());
+            let name_in_toml = name_in_toml.to_owned();
+            locations[&DependencyKey {
+                kind,
+                platform,
+                name_in_toml,
+            }]
+        };
+        assert_eq!(locations.len(), 5);
+        assert_eq!(location(dependency::Kind::Normal, None, "serde"), (6, 1));
+        assert_eq!(
+            location(dependency::Kind::Normal, None, "serde_json"),
+            (7, 1)
+        );
+        assert_eq!(location(dependency::Kind::Normal, None, "regex"), (9, 15));
+        assert_eq!(
+            location(dependency::Kind::Development, None, "maplit"),
+            (13, 1)
+        );
+        assert_eq!(
+            location(dependency::Kind::Build, Some("cfg(unix)"), "cc"),
+            (16, 1),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_dependencies() -> Fallible<()> {
+        static MANIFEST: &str = r#"[package]
+name = "foo"
+version = "0.0.0"
+
+[dependencies]
+# used
+serde = "1"
+serde_json = "1" # unused
+
+[target.'cfg(unix)'.dependencies]
+libc = "0.2"
+
+[target.'cfg(windows)'.dependencies]
+kernel32-sys = "0.2"
+winapi = "0.3"
+
+[features]
+default = []
+"#;
+
+        let key = |platform: Option<&str>, name_in_toml: &str| DependencyKey {
+            kind: dependency::Kind::Normal,
+            platform: platform.map(|p| p.parse().unwrap()),
+            name_in_toml: name_in_toml.to_owned(),
+        };
+        let fixed = super::remove_dependencies(
+            MANIFEST,
+            &[
+                key(None, "serde_json"),
+                key(Some("cfg(unix)"), "libc"),
+                key(Some("cfg(windows)"), "kernel32-sys"),
+            ],
+        )?;
+        assert_eq!(
+            fixed,
+            r#"[package]
+name = "foo"
+version = "0.0.0"
+
+[dependencies]
+# used
+serde = "1"
+
+[target.'cfg(windows)'.dependencies]
+winapi = "0.3"
+
+[features]
+default = []
+"#,
+        );
+        assert!(super::remove_dependencies(MANIFEST, &[key(None, "libc")]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_features_referring_to() -> Fallible<()> {
+        static MANIFEST: &str = r#"[features]
+default = ["a"]
+a = ["serde/derive"]
+b = ["dep:serde_json"]
+c = ["serde_json?/std"]
+"#;
+
+        assert_eq!(super::features_referring_to(MANIFEST, "serde")?, &["a"]);
+        assert_eq!(
+            super::features_referring_to(MANIFEST, "serde_json")?,
+            &["b", "c"],
+        );
+        assert!(super::features_referring_to(MANIFEST, "regex")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_features_enabling() -> Fallible<()> {
+        static MANIFEST: &str = r#"[dependencies]
+a = { path = "../a", features = ["serde"] }
+
+[target.'cfg(unix)'.dev-dependencies]
+b = { package = "a", path = "../a", features = ["regex"] }
+
+[features]
+default = []
+json = ["a/serde_json"]
+yaml = ["b?/serde_yaml"]
+"#;
+
+        let places = |feature| super::features_enabling(MANIFEST, "a", feature);
+        assert_eq!(places("serde")?, &["dependencies.a"]);
+        assert_eq!(
+            places("regex")?,
+            &[r#"target."cfg(unix)".dev-dependencies.b"#],
+        );
+        assert_eq!(places("serde_json")?, &["features.json"]);
+        assert_eq!(places("serde_yaml")?, &["features.yaml"]);
+        assert!(places("log")?.is_empty());
+        assert!(super::features_enabling(MANIFEST, "c", "serde")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_linked_metadata() -> Fallible<()> {
+        static MANIFEST: &str = r#"[package.metadata.cargo-linked]
+always-used = ["openssl-probe"]
+never = ["openssl-sys:0.9.53"]
+
+[workspace.metadata.cargo-linked]
+ignore = ["rustc-workspace-hack"]
+"#;
+
+        let metadata = super::linked_metadata(MANIFEST, "package")?;
+        assert_eq!(metadata.always_used, &["openssl-probe"]);
+        assert!(metadata.ignore.is_empty());
+        assert_eq!(metadata.never, &["openssl-sys:0.9.53"]);
+        let metadata = super::linked_metadata(MANIFEST, "workspace")?;
+        assert_eq!(metadata.ignore, &["rustc-workspace-hack"]);
+        let invalid = "[package.metadata.cargo-linked]\nignore = \"foo\"\n";
+        assert!(super::linked_metadata(invalid, "package").is_err());
+        Ok(())
+    }
+}
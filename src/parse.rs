@@ -1,137 +1,327 @@
 use cargo::core::manifest::TargetSourcePath;
-use cargo::core::Edition;
-use failure::Fallible;
+use cargo_platform::{Cfg, CfgExpr};
+use failure::{format_err, Fallible, ResultExt as _};
 use maplit::{btreeset, hashset};
+use syn::punctuated::Punctuated;
 use syn::visit::{self, Visit};
-use syn::{Item, ItemMod, ItemUse, UseTree};
+use syn::{
+    Attribute, Expr, ExprLit, ExprMacro, ImplItem, Item, ItemExternCrate, ItemMod, ItemUse, Lit,
+    Macro, Meta, NestedMeta, Token, UseTree,
+};
 
-use std::collections::{BTreeSet, HashSet};
-use std::path::Path;
+use std::collections::HashSet;
+use std::mem;
+use std::path::PathBuf;
+use std::str::{self, FromStr};
 
-pub(crate) fn find_uses_lossy<'a>(
-    src: &TargetSourcePath,
-    extern_crates: &HashSet<&'a str>,
-    edition: Edition,
-) -> Fallible<HashSet<&'a str>> {
-    match edition {
-        Edition::Edition2015 => find_uses_lossy_2015(src, extern_crates),
-        Edition::Edition2018 => find_uses_lossy_2018(src, extern_crates),
+/// The editions rustc accepts, which may be newer than the ones cargo 0.41 knows.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Edition {
+    Edition2015,
+    Edition2018,
+    Edition2021,
+    Edition2024,
+}
+
+impl From<cargo::core::Edition> for Edition {
+    fn from(edition: cargo::core::Edition) -> Self {
+        match edition {
+            cargo::core::Edition::Edition2015 => Edition::Edition2015,
+            cargo::core::Edition::Edition2018 => Edition::Edition2018,
+        }
     }
 }
 
-fn find_uses_lossy_2015<'a>(
-    src: &TargetSourcePath,
-    extern_crates: &HashSet<&'a str>,
-) -> Fallible<HashSet<&'a str>> {
-    let root_path = match src.path() {
-        None => return Ok(hashset!()),
-        Some(path) => path,
-    };
-    let file = crate::fs::read_src(root_path)?;
-    if !file.attrs.is_empty() {
-        return Ok(hashset!());
+impl FromStr for Edition {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "2015" => Ok(Edition::Edition2015),
+            "2018" => Ok(Edition::Edition2018),
+            "2021" => Ok(Edition::Edition2021),
+            "2024" => Ok(Edition::Edition2024),
+            s => Err(format_err!("Unknown edition: {:?}", s)),
+        }
     }
-    Ok(file
-        .items
-        .into_iter()
-        .flat_map(|item| match item {
-            Item::ExternCrate(item) => Some(item),
-            _ => None,
-        })
-        .filter(|item| item.attrs.is_empty())
-        .flat_map(|item| extern_crates.get(&*item.ident.to_string()).cloned())
-        .collect())
 }
 
-fn find_uses_lossy_2018<'a>(
+/// `source_files` are the files listed in the dep-info of the unit. If `None`, the modules are
+/// followed from `src`.
+///
+/// In 2015, the paths in the `use`s and the ones starting with `::` are relative to the crate
+/// root, where the extern crates are only through `extern crate` items. The other paths reach
+/// the extern prelude since Rust 1.30, as in 2018. 2021 and 2024 changed the preludes, not how
+/// the paths to the extern crates work.
+pub(crate) fn find_uses_lossy<'a>(
     src: &TargetSourcePath,
+    source_files: Option<&[PathBuf]>,
     extern_crates: &HashSet<&'a str>,
+    edition: Edition,
+    cfgs: &[Cfg],
+    env: &dyn Fn(&str) -> Option<String>,
 ) -> Fallible<HashSet<&'a str>> {
     struct Visitor<'a, 'b> {
         extern_crates: &'b HashSet<&'a str>,
+        // Whether the `use`s and the paths starting with `::` are relative to the crate root.
+        crate_relative: bool,
+        cfgs: &'b [Cfg],
+        env: &'b dyn Fn(&str) -> Option<String>,
+        // Whether to follow the `mod`s and the `include!`s.
+        follow: bool,
+        // The directory of the file being visited. `include!`s are relative to it.
+        file_dir: PathBuf,
+        // The directory for the `mod`s in the file, and the inline `mod`s being visited.
+        mod_dir: PathBuf,
+        inline_mods: Vec<String>,
         used: HashSet<&'a str>,
-        mods: BTreeSet<String>,
+        // The files of the `mod`s, with the directories for their `mod`s.
+        mods: Vec<(PathBuf, PathBuf)>,
+        // The file and the inline `mod`s being visited.
+        scopes: Vec<Scope<'a>>,
+        error: Option<failure::Error>,
+    }
+
+    // A module. The names bound in the blocks are regarded as bound in the whole module.
+    #[derive(Default)]
+    struct Scope<'a> {
+        // The first segments of the paths, including the ones of the `use`s. They may refer to
+        // the names bound in the module.
+        path_roots: HashSet<&'a str>,
+        // The names bound by the `mod`s, the `use`s and the renaming `extern crate`s.
+        local_names: HashSet<String>,
+    }
+
+    impl<'a> Visitor<'a, '_> {
+        fn scope(&mut self) -> &mut Scope<'a> {
+            self.scopes.last_mut().expect("should be in a module")
+        }
+
+        fn close_scope(&mut self) {
+            let Scope {
+                path_roots,
+                local_names,
+            } = self.scopes.pop().expect("should be in a module");
+            self.used
+                .extend(path_roots.into_iter().filter(|r| !local_names.contains(*r)));
+        }
+
+        fn resolve_mod(&self, item: &ItemMod) -> Fallible<(PathBuf, PathBuf)> {
+            let dir = self
+                .mod_dir
+                .join(self.inline_mods.iter().collect::<PathBuf>());
+            if let Some(path) = path_attr(&item.attrs, self.cfgs) {
+                // Files loaded by `#[path]` are treated as `mod.rs`s.
+                let base = if self.inline_mods.is_empty() {
+                    &self.file_dir
+                } else {
+                    &dir
+                };
+                let path = base.join(path);
+                if !path.exists() {
+                    return Err(failure::err_msg(format!("No such file: {:?}", path)));
+                }
+                let mod_dir = path.parent().unwrap_or(&dir).to_owned();
+                return Ok((path, mod_dir));
+            }
+
+            let name = item.ident.to_string();
+            let (path, another_path) = (
+                dir.join(&name).with_extension("rs"),
+                dir.join(&name).join("mod.rs"),
+            );
+            if path.exists() {
+                Ok((path, dir.join(&name)))
+            } else if another_path.exists() {
+                Ok((another_path, dir.join(&name)))
+            } else {
+                Err(failure::err_msg(format!(
+                    "No such file: {:?}",
+                    btreeset!(path, another_path),
+                )))
+            }
+        }
+
+        fn visit_included(&mut self, arg: &Expr) -> Fallible<()> {
+            let path = eval_str(arg, self.env)
+                .ok_or_else(|| failure::err_msg("Could not evaluate the argument of `include!`"))?;
+            let path = self.file_dir.join(path);
+            let src = std::fs::read_to_string(&path)
+                .with_context(|_| failure::err_msg(format!("Failed to read {}", path.display())))?;
+            let file_dir = path.parent().unwrap_or(&self.file_dir).to_owned();
+            let file_dir = mem::replace(&mut self.file_dir, file_dir);
+            if let Ok(file) = syn::parse_file(&src) {
+                self.visit_file(&file);
+            } else {
+                let expr = syn::parse_str::<Expr>(&src).with_context(|_| {
+                    failure::err_msg(format!("Failed to parse {}", path.display()))
+                })?;
+                self.visit_expr(&expr);
+            }
+            self.file_dir = file_dir;
+            Ok(())
+        }
     }
 
     impl<'a, 'b, 'ast> Visit<'ast> for Visitor<'a, 'b> {
+        fn visit_item(&mut self, item: &'ast Item) {
+            if is_active(item_attrs(item), self.cfgs) {
+                visit::visit_item(self, item);
+            }
+        }
+
+        fn visit_impl_item(&mut self, item: &'ast ImplItem) {
+            if is_active(impl_item_attrs(item), self.cfgs) {
+                visit::visit_impl_item(self, item);
+            }
+        }
+
         fn visit_item_mod(&mut self, item: &'ast ItemMod) {
-            if item.attrs.is_empty() {
-                if let Some((_, items)) = &item.content {
-                    let used = uses_of_extern_crates(items, self.extern_crates);
-                    self.used.extend(used);
-                } else {
-                    self.mods.insert(item.ident.to_string());
+            self.scope().local_names.insert(item.ident.to_string());
+            if item.content.is_some() {
+                let dir =
+                    path_attr(&item.attrs, self.cfgs).unwrap_or_else(|| item.ident.to_string());
+                self.inline_mods.push(dir);
+                self.scopes.push(Scope::default());
+                visit::visit_item_mod(self, item);
+                self.close_scope();
+                self.inline_mods.pop();
+            } else {
+                if self.follow {
+                    match self.resolve_mod(item) {
+                        Ok(m) => self.mods.push(m),
+                        Err(err) => self.error = Some(err),
+                    }
                 }
                 visit::visit_item_mod(self, item);
             }
         }
 
+        // e.g. `use tracing::log;`, which shadows `log`
         fn visit_item_use(&mut self, item: &'ast ItemUse) {
-            let used = use_of_extern_crate(item, self.extern_crates);
+            if !self.crate_relative {
+                let root = use_of_extern_crate(item, self.extern_crates, self.cfgs);
+                self.scope().path_roots.extend(root);
+            }
+            use_bindings(&item.tree, true, &mut self.scope().local_names);
+        }
+
+        // Including the ones with `#[macro_use]`.
+        fn visit_item_extern_crate(&mut self, item: &'ast ItemExternCrate) {
+            let used = self.extern_crates.get(&*item.ident.to_string()).cloned();
             self.used.extend(used);
+            if let Some((_, rename)) = &item.rename {
+                self.scope().local_names.insert(rename.to_string());
+            }
         }
-    }
 
-    let root_path = match src.path() {
-        None => return Ok(hashset!()),
-        Some(path) => path.to_owned(),
-    };
-    let (mut mods, mut used) = (btreeset!(vec![]), hashset!());
-
-    while !mods.is_empty() {
-        let mut next_mods = btreeset!();
-        for mods in mods {
-            let path = {
-                let mut path = root_path.clone();
-                let mut mods = mods.iter().peekable();
-                if mods.peek().is_some() {
-                    path.pop();
-                }
-                let mut another_path = None;
-                while let Some(m) = mods.next() {
-                    if mods.peek().is_some() {
-                        path.push(m);
-                    } else {
-                        another_path = Some(path.join(m).join("mod.rs"));
-                        path.push(Path::new(m).with_extension("rs"));
-                    }
+        fn visit_path(&mut self, path: &'ast syn::Path) {
+            if path.leading_colon.is_some() && !self.crate_relative
+                || path.leading_colon.is_none() && path.segments.len() > 1
+            {
+                if let Some(first) = path.segments.first() {
+                    let root = self.extern_crates.get(&*first.ident.to_string()).cloned();
+                    self.scope().path_roots.extend(root);
                 }
-                if path.exists() {
-                    path
-                } else if let Some(another_path) = another_path {
-                    if another_path.exists() {
-                        another_path
-                    } else {
-                        return Err(failure::err_msg(format!(
-                            "No such file: {:?}",
-                            btreeset!(path, another_path),
-                        )));
+            }
+            visit::visit_path(self, path);
+        }
+
+        // e.g. `#[derive(serde::Deserialize)]`
+        fn visit_attribute(&mut self, attr: &'ast Attribute) {
+            match attr.parse_meta() {
+                Ok(meta) => self.visit_meta(&meta),
+                Err(_) => visit::visit_attribute(self, attr),
+            }
+        }
+
+        // e.g. `println!("{}", serde_json::to_string(&value)?)`
+        fn visit_macro(&mut self, mac: &'ast Macro) {
+            visit::visit_macro(self, mac);
+            if let Ok(exprs) = mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+            {
+                if mac.path.is_ident("include") && exprs.len() == 1 {
+                    if self.follow {
+                        if let Err(err) = self.visit_included(&exprs[0]) {
+                            self.error = Some(err);
+                        }
                     }
                 } else {
-                    return Err(failure::err_msg(format!("No such file: {:?}", path)));
+                    exprs.iter().for_each(|expr| self.visit_expr(expr));
                 }
-            };
-            let file = crate::fs::read_src(&path)?;
-            let mut visitor = Visitor {
-                extern_crates,
-                used: uses_of_extern_crates(&file.items, extern_crates),
-                mods: btreeset!(),
-            };
-            visitor.visit_file(&file);
-            for m in visitor.mods {
-                let mut mods = mods.clone();
-                mods.push(m);
-                next_mods.insert(mods);
-            }
-            used.extend(visitor.used);
-        }
-        mods = next_mods;
+            }
+        }
+    }
+
+    let follow = source_files.is_none();
+    let mut files = match source_files {
+        Some(source_files) => source_files
+            .iter()
+            .map(|p| (p.clone(), p.parent().unwrap_or(p).to_owned()))
+            .collect(),
+        None => match src.path() {
+            None => return Ok(hashset!()),
+            Some(path) => vec![(path.to_owned(), path.parent().unwrap_or(path).to_owned())],
+        },
+    };
+    let (mut visited, mut used) = (hashset!(), hashset!());
+
+    while let Some((path, mod_dir)) = files.pop() {
+        if !visited.insert(path.clone()) {
+            continue;
+        }
+        let mut visitor = Visitor {
+            extern_crates,
+            crate_relative: edition == Edition::Edition2015,
+            cfgs,
+            env,
+            follow,
+            file_dir: path.parent().unwrap_or(&path).to_owned(),
+            mod_dir,
+            inline_mods: vec![],
+            used: hashset!(),
+            mods: vec![],
+            scopes: vec![Scope::default()],
+            error: None,
+        };
+        match crate::fs::read_src(&path) {
+            Ok(file) => visitor.visit_file(&file),
+            Err(err) if follow => return Err(err),
+            // The dep-info also lists the files `include!`d as expressions, and the ones
+            // `include_str!`d or `include_bytes!`d, which are not Rust at all.
+            Err(_) => {
+                let src = crate::fs::read(&path)?;
+                let expr = str::from_utf8(&src)
+                    .ok()
+                    .and_then(|s| syn::parse_str::<Expr>(s).ok());
+                match expr {
+                    Some(expr) => visitor.visit_expr(&expr),
+                    None => continue,
+                }
+            }
+        }
+        visitor.close_scope();
+        let Visitor {
+            used: file_used,
+            mods: file_mods,
+            error,
+            ..
+        } = visitor;
+        if let Some(error) = error {
+            return Err(error);
+        }
+        files.extend(file_mods);
+        used.extend(file_used);
     }
     Ok(used)
 }
 
-fn use_of_extern_crate<'a>(item: &ItemUse, extern_crates: &HashSet<&'a str>) -> Option<&'a str> {
-    if !item.attrs.is_empty() {
+fn use_of_extern_crate<'a>(
+    item: &ItemUse,
+    extern_crates: &HashSet<&'a str>,
+    cfgs: &[Cfg],
+) -> Option<&'a str> {
+    if !is_active(&item.attrs, cfgs) {
         return None;
     }
     let top = match &item.tree {
@@ -143,15 +333,169 @@ fn use_of_extern_crate<'a>(item: &ItemUse, extern_crates: &HashSet<&'a str>) ->
     extern_crates.get(&*top.to_string()).cloned()
 }
 
-fn uses_of_extern_crates<'a>(items: &[Item], extern_crates: &HashSet<&'a str>) -> HashSet<&'a str> {
-    items
-        .iter()
-        .flat_map(|item| match item {
-            Item::Use(item) => Some(item),
+/// Collects the names a `use` binds. `use serde;` and `use serde::{self};` bind the extern crate
+/// to its own name, which does not shadow it.
+fn use_bindings(tree: &UseTree, top: bool, acc: &mut HashSet<String>) {
+    match tree {
+        UseTree::Path(path) => use_bindings(&path.tree, false, acc),
+        UseTree::Name(name) if name.ident == "self" || top => {}
+        UseTree::Name(name) => {
+            acc.insert(name.ident.to_string());
+        }
+        UseTree::Rename(rename) if top && rename.ident == rename.rename => {}
+        UseTree::Rename(rename) => {
+            acc.insert(rename.rename.to_string());
+        }
+        UseTree::Group(group) => group.items.iter().for_each(|t| use_bindings(t, top, acc)),
+        UseTree::Glob(_) => {}
+    }
+}
+
+/// Returns `false` if any of the `#[cfg]`s, including the ones from `#[cfg_attr]`s, is not
+/// satisfied. Predicates which cannot be understood are regarded as satisfied.
+fn is_active(attrs: &[Attribute], cfgs: &[Cfg]) -> bool {
+    expand_cfg_attrs(attrs, cfgs).iter().all(|meta| match meta {
+        Meta::List(list) if list.path.is_ident("cfg") => list
+            .nested
+            .iter()
+            .all(|pred| cfg_expr(pred).map(|e| e.matches(cfgs)) != Some(false)),
+        _ => true,
+    })
+}
+
+/// Parses the attributes, replacing the `#[cfg_attr]`s with the attributes they enable.
+fn expand_cfg_attrs(attrs: &[Attribute], cfgs: &[Cfg]) -> Vec<Meta> {
+    fn expand(meta: Meta, cfgs: &[Cfg], acc: &mut Vec<Meta>) {
+        match meta {
+            Meta::List(list) if list.path.is_ident("cfg_attr") => {
+                let mut nested = list.nested.into_iter();
+                let enabled = nested
+                    .next()
+                    .and_then(|pred| cfg_expr(&pred))
+                    .map(|e| e.matches(cfgs))
+                    != Some(false);
+                if enabled {
+                    for meta in nested {
+                        if let NestedMeta::Meta(meta) = meta {
+                            expand(meta, cfgs, acc);
+                        }
+                    }
+                }
+            }
+            meta => acc.push(meta),
+        }
+    }
+
+    let mut acc = vec![];
+    for attr in attrs {
+        if let Ok(meta) = attr.parse_meta() {
+            expand(meta, cfgs, &mut acc);
+        }
+    }
+    acc
+}
+
+/// Returns the value of the active `#[path]`.
+fn path_attr(attrs: &[Attribute], cfgs: &[Cfg]) -> Option<String> {
+    expand_cfg_attrs(attrs, cfgs)
+        .into_iter()
+        .flat_map(|meta| match meta {
+            Meta::NameValue(name_value) if name_value.path.is_ident("path") => {
+                match name_value.lit {
+                    Lit::Str(value) => Some(value.value()),
+                    _ => None,
+                }
+            }
             _ => None,
         })
-        .flat_map(|item| use_of_extern_crate(item, extern_crates))
-        .collect()
+        .last()
+}
+
+/// Evaluates string literals, `concat!`s and `env!`s.
+fn eval_str(expr: &Expr, env: &dyn Fn(&str) -> Option<String>) -> Option<String> {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(value),
+            ..
+        }) => Some(value.value()),
+        Expr::Macro(ExprMacro { mac, .. }) => {
+            let args = mac
+                .parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated)
+                .ok()?;
+            if mac.path.is_ident("concat") {
+                args.iter().map(|arg| eval_str(arg, env)).collect()
+            } else if mac.path.is_ident("env") {
+                env(&eval_str(args.first()?, env)?)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn cfg_expr(pred: &NestedMeta) -> Option<CfgExpr> {
+    let meta = match pred {
+        NestedMeta::Meta(meta) => meta,
+        NestedMeta::Lit(_) => return None,
+    };
+    match meta {
+        Meta::Path(path) => Some(CfgExpr::Value(Cfg::Name(path.get_ident()?.to_string()))),
+        Meta::NameValue(name_value) => match &name_value.lit {
+            Lit::Str(value) => Some(CfgExpr::Value(Cfg::KeyPair(
+                name_value.path.get_ident()?.to_string(),
+                value.value(),
+            ))),
+            _ => None,
+        },
+        Meta::List(list) => {
+            let exprs = list
+                .nested
+                .iter()
+                .map(cfg_expr)
+                .collect::<Option<Vec<_>>>()?;
+            match &*list.path.get_ident()?.to_string() {
+                "all" => Some(CfgExpr::All(exprs)),
+                "any" => Some(CfgExpr::Any(exprs)),
+                "not" if exprs.len() == 1 => {
+                    Some(CfgExpr::Not(Box::new(exprs.into_iter().next()?)))
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+fn item_attrs(item: &Item) -> &[Attribute] {
+    match item {
+        Item::Const(item) => &item.attrs,
+        Item::Enum(item) => &item.attrs,
+        Item::ExternCrate(item) => &item.attrs,
+        Item::Fn(item) => &item.attrs,
+        Item::ForeignMod(item) => &item.attrs,
+        Item::Impl(item) => &item.attrs,
+        Item::Macro(item) => &item.attrs,
+        Item::Macro2(item) => &item.attrs,
+        Item::Mod(item) => &item.attrs,
+        Item::Static(item) => &item.attrs,
+        Item::Struct(item) => &item.attrs,
+        Item::Trait(item) => &item.attrs,
+        Item::TraitAlias(item) => &item.attrs,
+        Item::Type(item) => &item.attrs,
+        Item::Union(item) => &item.attrs,
+        Item::Use(item) => &item.attrs,
+        _ => &[],
+    }
+}
+
+fn impl_item_attrs(item: &ImplItem) -> &[Attribute] {
+    match item {
+        ImplItem::Const(item) => &item.attrs,
+        ImplItem::Method(item) => &item.attrs,
+        ImplItem::Type(item) => &item.attrs,
+        ImplItem::Macro(item) => &item.attrs,
+        _ => &[],
+    }
 }
 
 #[cfg(test)]
@@ -160,19 +504,329 @@ mod tests {
     use failure::Fallible;
     use maplit::hashset;
     use once_cell::sync::Lazy;
+    use tempdir::TempDir;
 
     use std::collections::HashSet;
 
     #[test]
-    fn test_find_uses_lossy_2018() -> Fallible<()> {
+    fn test_find_uses_lossy() -> Fallible<()> {
         static PATH: Lazy<TargetSourcePath> = Lazy::new(|| TargetSourcePath::Path(file!().into()));
         static EXTERN_CRATES: Lazy<HashSet<&str>> =
             Lazy::new(|| hashset!("cargo", "failure", "maplit", "once_cell", "syn"));
         static EXPECTED: Lazy<HashSet<&str>> =
             Lazy::new(|| hashset!("cargo", "failure", "maplit", "syn"));
 
-        let used = super::find_uses_lossy_2018(&PATH, &EXTERN_CRATES)?;
+        let used = super::find_uses_lossy(
+            &PATH,
+            None,
+            &EXTERN_CRATES,
+            super::Edition::Edition2018,
+            &[],
+            &|_| None,
+        )?;
         assert_eq!(used, *EXPECTED);
         Ok(())
     }
+
+    #[test]
+    fn test_find_uses_lossy_paths() -> Fallible<()> {
+        static SRC: &str = r#"#[derive(serde::Deserialize)]
+struct Foo {
+    regex: regex::Regex,
+}
+
+fn main() {
+    log::info!("{}", serde_json::to_string(&itertools::join(&[1], "")).unwrap());
+    ::anyhow::bail!("");
+    rand::foo();
+}
+
+mod rand {
+    pub(crate) fn foo() {}
+}
+"#;
+
+        let tempdir = TempDir::new("cargo-linked-test-parse")?;
+        let path = tempdir.path().join("main.rs");
+        std::fs::write(&path, SRC)?;
+        let extern_crates = hashset!(
+            "anyhow",
+            "itertools",
+            "log",
+            "rand",
+            "regex",
+            "serde",
+            "serde_json",
+            "structopt",
+        );
+        let used = super::find_uses_lossy(
+            &TargetSourcePath::Path(path),
+            None,
+            &extern_crates,
+            super::Edition::Edition2018,
+            &[],
+            &|_| None,
+        )?;
+        assert_eq!(
+            used,
+            hashset!("anyhow", "itertools", "log", "regex", "serde", "serde_json"),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_uses_lossy_use_bindings() -> Fallible<()> {
+        let tempdir = TempDir::new("cargo-linked-test-parse")?;
+        let path = |name: &str| tempdir.path().join(name);
+        std::fs::write(
+            path("lib.rs"),
+            r#"use tracing::log;
+use anyhow::{self as serde, Context as _};
+use regex;
+
+mod a;
+
+mod b {
+    fn h() {
+        nom::x();
+    }
+}
+
+fn f() {
+    log::info!("");
+    serde::x();
+}
+"#,
+        )?;
+        std::fs::write(
+            path("a.rs"),
+            r#"use tokio::net as nom;
+
+fn g() {
+    nom::x();
+    serde::x();
+    libc::getpid();
+}
+"#,
+        )?;
+
+        // The names are bound per module.
+        let extern_crates =
+            hashset!("anyhow", "libc", "log", "nom", "regex", "serde", "tokio", "tracing",);
+        let used = super::find_uses_lossy(
+            &TargetSourcePath::Path(path("lib.rs")),
+            None,
+            &extern_crates,
+            super::Edition::Edition2018,
+            &[],
+            &|_| None,
+        )?;
+        assert_eq!(
+            used,
+            hashset!("anyhow", "libc", "nom", "regex", "serde", "tokio", "tracing"),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_uses_lossy_cfg() -> Fallible<()> {
+        static SRC: &str = r#"#[cfg(unix)]
+use nix::unistd;
+#[cfg(windows)]
+use winapi::um;
+#[cfg(all(feature = "json", not(test)))]
+use serde_json::Value;
+#[cfg_attr(feature = "yaml", cfg(any()))]
+use serde_yaml::Value;
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+}
+
+#[cfg(unix)]
+fn f() {
+    libc::getpid();
+}
+"#;
+
+        let tempdir = TempDir::new("cargo-linked-test-parse")?;
+        let path = tempdir.path().join("lib.rs");
+        std::fs::write(&path, SRC)?;
+        let extern_crates = hashset!("libc", "nix", "regex", "serde_json", "serde_yaml", "winapi");
+        let cfgs = &[
+            "unix".parse()?,
+            r#"feature="json""#.parse()?,
+            r#"feature="yaml""#.parse()?,
+        ];
+        let used = super::find_uses_lossy(
+            &TargetSourcePath::Path(path),
+            None,
+            &extern_crates,
+            super::Edition::Edition2018,
+            cfgs,
+            &|_| None,
+        )?;
+        assert_eq!(used, hashset!("libc", "nix", "serde_json"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_uses_lossy_path_and_include() -> Fallible<()> {
+        let tempdir = TempDir::new("cargo-linked-test-parse")?;
+        let write = |path: &str, content: &str| -> Fallible<()> {
+            let path = tempdir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, content).map_err(Into::into)
+        };
+        write(
+            "src/lib.rs",
+            r#"#[path = "generated/a.rs"]
+mod a;
+mod b;
+mod c {
+    mod d;
+}
+include!(concat!(env!("OUT_DIR"), "/e.rs"));
+"#,
+        )?;
+        write("src/generated/a.rs", "mod f;\nuse itertools::Itertools;\n")?;
+        write("src/generated/f.rs", "use libc::c_int;\n")?;
+        write("src/b/mod.rs", "use nix::unistd;\n")?;
+        write("src/c/d.rs", "use regex::Regex;\n")?;
+        write("out/e.rs", "fn e() -> serde_json::Value { todo!() }\n")?;
+
+        let extern_crates = hashset!(
+            "itertools",
+            "libc",
+            "nix",
+            "regex",
+            "serde_json",
+            "structopt"
+        );
+        let out_dir = tempdir.path().join("out").to_str().unwrap().to_owned();
+        let env = |key: &str| Some(out_dir.clone()).filter(|_| key == "OUT_DIR");
+        let src_path = TargetSourcePath::Path(tempdir.path().join("src").join("lib.rs"));
+        let used = super::find_uses_lossy(
+            &src_path,
+            None,
+            &extern_crates,
+            super::Edition::Edition2018,
+            &[],
+            &env,
+        )?;
+        assert_eq!(
+            used,
+            hashset!("itertools", "libc", "nix", "regex", "serde_json"),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_uses_lossy_source_files() -> Fallible<()> {
+        let tempdir = TempDir::new("cargo-linked-test-parse")?;
+        let path = |name: &str| tempdir.path().join(name);
+        std::fs::write(path("lib.rs"), "#[cfg(any())]\nmod a;\nmod b;\nmod rand;\n")?;
+        std::fs::write(path("a.rs"), "use libc::c_int;\n")?;
+        std::fs::write(
+            path("b.rs"),
+            "use crate::rand;\nuse nix::unistd;\nfn f() { rand::f(); }\n",
+        )?;
+        std::fs::write(path("rand.rs"), "pub(crate) fn f() {}\n")?;
+        std::fs::write(path("c.rs"), "regex::Regex::new(\"\")\n")?;
+        std::fs::write(
+            path("bindings.in"),
+            "fn g() -> bitflags::Flags { todo!() }\n",
+        )?;
+        std::fs::write(path("d.txt"), "not Rust\n")?;
+
+        let extern_crates = hashset!("bitflags", "libc", "nix", "rand", "regex");
+        let source_files = &[
+            path("lib.rs"),
+            path("b.rs"),
+            path("rand.rs"),
+            path("c.rs"),
+            path("bindings.in"),
+            path("d.txt"),
+        ];
+        let src_path = TargetSourcePath::Path(path("lib.rs"));
+        let used = super::find_uses_lossy(
+            &src_path,
+            Some(source_files),
+            &extern_crates,
+            super::Edition::Edition2018,
+            &[],
+            &|_| None,
+        )?;
+        assert_eq!(used, hashset!("bitflags", "nix", "regex"));
+
+        let missing = &[path("lib.rs"), path("missing.rs")];
+        let used = super::find_uses_lossy(
+            &src_path,
+            Some(missing),
+            &extern_crates,
+            super::Edition::Edition2018,
+            &[],
+            &|_| None,
+        );
+        assert!(used.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_edition_from_str() {
+        use super::Edition;
+
+        assert_eq!("2015".parse::<Edition>().ok(), Some(Edition::Edition2015));
+        assert_eq!("2021".parse::<Edition>().ok(), Some(Edition::Edition2021));
+        assert_eq!("2024".parse::<Edition>().ok(), Some(Edition::Edition2024));
+        assert!("2027".parse::<Edition>().is_err());
+    }
+
+    #[test]
+    fn test_find_uses_lossy_2015() -> Fallible<()> {
+        let tempdir = TempDir::new("cargo-linked-test-parse")?;
+        let path = |name: &str| tempdir.path().join(name);
+        std::fs::write(
+            path("lib.rs"),
+            r#"#![deny(warnings)]
+
+#[macro_use]
+extern crate log;
+extern crate regex as re;
+#[cfg(any())]
+extern crate libc;
+
+use serde::Deserialize;
+
+mod a;
+
+fn f() {
+    ::anyhow::bail!("");
+    itertools::join(&[1], "");
+}
+"#,
+        )?;
+        std::fs::write(path("a.rs"), "extern crate nix;\n")?;
+
+        let extern_crates = hashset!(
+            "anyhow",
+            "itertools",
+            "libc",
+            "log",
+            "nix",
+            "regex",
+            "serde"
+        );
+        let used = super::find_uses_lossy(
+            &TargetSourcePath::Path(path("lib.rs")),
+            None,
+            &extern_crates,
+            super::Edition::Edition2015,
+            &[],
+            &|_| None,
+        )?;
+        assert_eq!(used, hashset!("itertools", "log", "nix", "regex"));
+        Ok(())
+    }
 }
@@ -0,0 +1,400 @@
+//! The denylist for `--policy`, and the `never` policies of the members.
+//!
+//! ```toml
+//! [deny]
+//! packages = ["openssl", "openssl-*", "time@<0.2"]
+//! licenses = ["GPL-*", "AGPL-*"]
+//! ```
+
+use crate::{LinkedEdge, LinkedOutcome, LinkedPackages, UsedAs};
+
+use cargo::core::PackageId;
+use cargo::CargoResult;
+use failure::{format_err, ResultExt as _};
+use semver::VersionReq;
+use toml_edit::ImDocument;
+
+use std::path::Path;
+use std::str::FromStr;
+
+/// Packages that must not be linked into the artifacts.
+///
+/// Only the packages linked into `lib`s and `bin`s are checked. Proc-macros, build
+/// dependencies and dev-dependencies are allowed.
+#[derive(Debug, Default)]
+pub struct Denylist {
+    pub packages: Vec<PackagePattern>,
+    /// SPDX license IDs, which may contain `*`s.
+    pub licenses: Vec<String>,
+}
+
+impl Denylist {
+    pub fn read(path: &Path) -> CargoResult<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format_err!("Failed to read {}: {}", path.display(), err))?;
+        Self::parse(&text)
+            .with_context(|_| format_err!("Failed to parse {}", path.display()))
+            .map_err(Into::into)
+    }
+
+    fn parse(text: &str) -> CargoResult<Self> {
+        let doc = ImDocument::parse(text)?;
+        let strings = |key: &str| -> CargoResult<Vec<String>> {
+            match doc.get("deny").and_then(|d| d.get(key)) {
+                None => Ok(vec![]),
+                Some(item) => item
+                    .as_array()
+                    .and_then(|a| {
+                        a.iter()
+                            .map(|v| v.as_str().map(ToOwned::to_owned))
+                            .collect()
+                    })
+                    .ok_or_else(|| format_err!("`deny.{}` must be an array of strings", key)),
+            }
+        };
+        let packages = strings("packages")?
+            .iter()
+            .map(|s| s.parse())
+            .collect::<CargoResult<_>>()?;
+        let licenses = strings("licenses")?;
+        Ok(Self { packages, licenses })
+    }
+
+    /// Returns the reason if `id` is denied.
+    pub fn denies(&self, id: PackageId, license: Option<&str>) -> Option<String> {
+        if let Some(pattern) = self.packages.iter().find(|p| p.matches(id)) {
+            return Some(format!("matches `{}`", pattern.original));
+        }
+        let license = license?;
+        let is_denied = |id: &str| {
+            let id = id.trim_end_matches('+');
+            self.licenses.iter().any(|p| wildcard_match(p, id))
+        };
+        if license_avoidable(license, &is_denied) {
+            return None;
+        }
+        Some(format!("licensed under `{}`", license))
+    }
+
+    /// Checks the `lib`s and the `bin`s of the members.
+    pub fn check(&self, outcome: &LinkedOutcome) -> Vec<Violation> {
+        let licenses = &outcome.metadata.licenses;
+        violations(outcome, Some(&["lib", "bin"]), |_, id| {
+            self.denies(id, licenses.get(&id).map(|s| &**s))
+        })
+    }
+}
+
+/// Checks the targets against the `never` policies of the members.
+pub fn forbidden(outcome: &LinkedOutcome) -> Vec<Violation> {
+    violations(outcome, None, |linked, id| {
+        if linked.forbidden.contains(&id) {
+            Some("forbidden by the `never` policy".to_owned())
+        } else {
+            None
+        }
+    })
+}
+
+/// Collects the packages linked at runtime into the targets of the `kinds`, or into all of the
+/// targets if `None`, which `reason` rejects.
+fn violations(
+    outcome: &LinkedOutcome,
+    kinds: Option<&[&str]>,
+    reason: impl Fn(&LinkedPackages, PackageId) -> Option<String>,
+) -> Vec<Violation> {
+    let mut violations = vec![];
+    for (triple, workspace) in &outcome.platforms {
+        for (&member, linked) in &workspace.members {
+            let outcomes = if linked.targets.is_empty() {
+                vec![linked]
+            } else {
+                linked
+                    .targets
+                    .iter()
+                    .filter(|(k, _)| kinds.iter().all(|kinds| kinds.contains(&&***k)))
+                    .flat_map(|(_, t)| t.values())
+                    .collect()
+            };
+            for linked in outcomes {
+                let runtime = linked
+                    .used_as
+                    .iter()
+                    .filter(|&(&id, &used_as)| id != member && used_as == UsedAs::Runtime);
+                for (&id, _) in runtime {
+                    if let Some(reason) = reason(linked, id) {
+                        // Without a path if `id` is not reached from the root target.
+                        let path = linked
+                            .why(id)
+                            .into_iter()
+                            .flatten()
+                            .map(|(edge, to)| (edge.clone(), to))
+                            .collect();
+                        violations.push(Violation {
+                            triple: triple.to_owned(),
+                            member,
+                            package: id,
+                            reason,
+                            path,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    violations
+}
+
+/// A denied package linked into an artifact.
+#[derive(Debug)]
+pub struct Violation {
+    pub triple: String,
+    pub member: PackageId,
+    pub package: PackageId,
+    pub reason: String,
+    /// A path from the root target of `member` to `package`. Empty if none was recorded.
+    pub path: Vec<(LinkedEdge, PackageId)>,
+}
+
+impl Violation {
+    /// e.g. `foo v0.1.0 (bin "foo") -> native-tls v0.2.3 -> openssl v0.10.26`
+    pub fn witness(&self) -> String {
+        let mut ret = self.member.to_string();
+        if let Some((edge, _)) = self.path.first() {
+            ret += &format!(" ({})", edge.target);
+        }
+        for (_, to) in &self.path {
+            ret += &format!(" -> {}", to);
+        }
+        ret
+    }
+}
+
+/// `NAME` or `NAME@VERSION_REQ`. `NAME` may contain `*`s.
+#[derive(Debug)]
+pub struct PackagePattern {
+    original: String,
+    name: String,
+    version_req: Option<VersionReq>,
+}
+
+impl PackagePattern {
+    pub fn matches(&self, id: PackageId) -> bool {
+        wildcard_match(&self.name, &id.name())
+            && self.version_req.iter().all(|r| r.matches(id.version()))
+    }
+}
+
+impl FromStr for PackagePattern {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, failure::Error> {
+        let (name, version_req) = match s.find('@') {
+            None => (s, None),
+            Some(i) => {
+                let req = VersionReq::parse(&s[i + 1..])
+                    .with_context(|_| format_err!("Invalid version requirement: {:?}", s))?;
+                (&s[..i], Some(req))
+            }
+        };
+        Ok(Self {
+            original: s.to_owned(),
+            name: name.to_owned(),
+            version_req,
+        })
+    }
+}
+
+/// Whether the SPDX license expression can be satisfied without any of the denied licenses.
+///
+/// The legacy `/` is read as `OR`. If the expression cannot be parsed, every license in it is
+/// considered required.
+fn license_avoidable(expr: &str, is_denied: &dyn Fn(&str) -> bool) -> bool {
+    let spaced = expr
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .replace('/', " OR ");
+    let tokens = spaced.split_whitespace().collect::<Vec<_>>();
+    let mut pos = 0;
+    match parse_or(&tokens, &mut pos, is_denied) {
+        Some(avoidable) if pos == tokens.len() => avoidable,
+        _ => !tokens
+            .iter()
+            .filter(|t| !["(", ")", "AND", "OR", "WITH"].contains(t))
+            .any(|t| is_denied(t)),
+    }
+}
+
+fn parse_or(tokens: &[&str], pos: &mut usize, is_denied: &dyn Fn(&str) -> bool) -> Option<bool> {
+    let mut avoidable = parse_and(tokens, pos, is_denied)?;
+    while tokens.get(*pos) == Some(&"OR") {
+        *pos += 1;
+        avoidable |= parse_and(tokens, pos, is_denied)?;
+    }
+    Some(avoidable)
+}
+
+fn parse_and(tokens: &[&str], pos: &mut usize, is_denied: &dyn Fn(&str) -> bool) -> Option<bool> {
+    let mut avoidable = parse_atom(tokens, pos, is_denied)?;
+    while tokens.get(*pos) == Some(&"AND") {
+        *pos += 1;
+        avoidable &= parse_atom(tokens, pos, is_denied)?;
+    }
+    Some(avoidable)
+}
+
+fn parse_atom(tokens: &[&str], pos: &mut usize, is_denied: &dyn Fn(&str) -> bool) -> Option<bool> {
+    match *tokens.get(*pos)? {
+        "(" => {
+            *pos += 1;
+            let avoidable = parse_or(tokens, pos, is_denied)?;
+            if tokens.get(*pos) != Some(&")") {
+                return None;
+            }
+            *pos += 1;
+            Some(avoidable)
+        }
+        ")" | "AND" | "OR" | "WITH" => None,
+        license => {
+            *pos += 1;
+            if tokens.get(*pos) == Some(&"WITH") {
+                tokens.get(*pos + 1)?;
+                *pos += 2;
+            }
+            Some(!is_denied(license))
+        }
+    }
+}
+
+/// Matches `s` against `pattern`, in which `*` matches any string.
+fn wildcard_match(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    if !s.starts_with(first) {
+        return false;
+    }
+    let mut rest = &s[first.len()..];
+    let parts = parts.collect::<Vec<_>>();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{id, outcome_for, versioned_id};
+    use crate::{LinkedEdge, LinkedPackages, UsedAs};
+
+    use cargo::CargoResult;
+    use maplit::{btreemap, btreeset};
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(super::wildcard_match("openssl", "openssl"));
+        assert!(!super::wildcard_match("openssl", "openssl-sys"));
+        assert!(super::wildcard_match("openssl-*", "openssl-sys"));
+        assert!(super::wildcard_match("GPL-*", "GPL-3.0-only"));
+        assert!(!super::wildcard_match("GPL-*", "LGPL-2.1"));
+        assert!(super::wildcard_match("*-sys", "libz-sys"));
+        assert!(super::wildcard_match("a*b*c", "abbc"));
+        assert!(!super::wildcard_match("a*bc", "abc*"));
+    }
+
+    #[test]
+    fn test_license_avoidable() {
+        let is_denied = |id: &str| id.starts_with("GPL-");
+        assert!(super::license_avoidable("MIT", &is_denied));
+        assert!(super::license_avoidable("MIT OR GPL-3.0", &is_denied));
+        assert!(super::license_avoidable("MIT/GPL-3.0", &is_denied));
+        assert!(!super::license_avoidable("MIT AND GPL-3.0", &is_denied));
+        assert!(!super::license_avoidable("GPL-2.0+", &is_denied));
+        assert!(super::license_avoidable(
+            "(GPL-2.0 WITH Classpath-exception-2.0 AND MIT) OR Apache-2.0",
+            &is_denied,
+        ));
+        assert!(!super::license_avoidable("MIT AND (GPL-2.0", &is_denied));
+    }
+
+    #[test]
+    fn test_denies() -> CargoResult<()> {
+        let denylist = super::Denylist::parse(
+            r#"[deny]
+packages = ["openssl", "time@<0.2"]
+licenses = ["GPL-*"]
+"#,
+        )?;
+        let id = versioned_id;
+        assert!(denylist.denies(id("openssl", "0.10.26"), None).is_some());
+        assert!(denylist.denies(id("openssl-sys", "0.9.53"), None).is_none());
+        assert!(denylist.denies(id("time", "0.1.42"), None).is_some());
+        assert!(denylist.denies(id("time", "0.2.0"), None).is_none());
+        assert!(denylist
+            .denies(id("foo", "1.0.0"), Some("GPL-3.0"))
+            .is_some());
+        assert!(denylist
+            .denies(id("foo", "1.0.0"), Some("MIT OR GPL-3.0"))
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_without_witness() -> CargoResult<()> {
+        let denylist = super::Denylist::parse("[deny]\npackages = [\"openssl\"]\n")?;
+        // `reached_via` is empty.
+        let linked = LinkedPackages {
+            used: btreeset!(id("openssl")),
+            used_as: btreemap!(id("openssl") => UsedAs::Runtime),
+            ..Default::default()
+        };
+        let violations = denylist.check(&outcome_for(btreemap!(id("foo") => linked)));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].package, id("openssl"));
+        assert!(violations[0].path.is_empty());
+        assert_eq!(violations[0].witness(), id("foo").to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forbidden() {
+        let edge = |from, extern_crate_name: &str, from_root| LinkedEdge {
+            from,
+            target: "bin \"foo\"".to_owned(),
+            extern_crate_name: extern_crate_name.to_owned(),
+            from_root,
+        };
+        let linked = LinkedPackages {
+            used: btreeset!(id("native-tls"), id("openssl")),
+            used_as: btreemap!(
+                id("native-tls") => UsedAs::Runtime,
+                id("openssl") => UsedAs::Runtime,
+            ),
+            forbidden: btreeset!(id("openssl")),
+            reached_via: btreemap!(
+                id("native-tls") => edge(id("foo"), "native_tls", true),
+                id("openssl") => edge(id("native-tls"), "openssl", false),
+            ),
+            ..Default::default()
+        };
+        let violations = super::forbidden(&outcome_for(btreemap!(id("foo") => linked)));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].reason, "forbidden by the `never` policy");
+        assert_eq!(
+            violations[0].witness(),
+            format!(
+                "{} (bin \"foo\") -> {} -> {}",
+                id("foo"),
+                id("native-tls"),
+                id("openssl"),
+            ),
+        );
+    }
+}
@@ -2,15 +2,19 @@ use cargo::core::manifest::Target;
 use cargo::core::package_id::PackageId;
 use cargo::util::errors::{CargoResult, ProcessError};
 use cargo::util::process_builder::ProcessBuilder;
+use cargo_platform::Cfg;
 use derive_more::Display;
 use failure::{Fallible, ResultExt as _};
 use fixedbitset::FixedBitSet;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use structopt::StructOpt;
+use tempdir::TempDir;
 
 use std::ffi::{OsStr, OsString};
+use std::mem;
 use std::ops::Range;
+use std::path::PathBuf;
 use std::process::Output;
 use std::str::{self, FromStr};
 
@@ -25,6 +29,20 @@ pub(crate) struct ErrorMessageCode {
     pub(crate) code: String,
 }
 
+impl ErrorMessage {
+    /// Whether the message is of the `unused_crate_dependencies` lint or says it is unknown.
+    fn is_about_unused_crate_dependencies(&self) -> bool {
+        match self.code.as_ref().map(|c| &*c.code) {
+            Some("unused_crate_dependencies") => true,
+            Some("E0602") | Some("unknown_lints") => {
+                self.message.contains("unused_crate_dependencies")
+                    || self.message.contains("unused-crate-dependencies")
+            }
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Rustc<'a> {
     cmd: ProcessBuilder,
@@ -51,6 +69,18 @@ impl<'a> Rustc<'a> {
         &self.opts.r#extern
     }
 
+    pub(crate) fn cfgs(&self, host: &[Cfg], target: &[Cfg]) -> Fallible<Vec<Cfg>> {
+        self.opts.cfgs(host, target)
+    }
+
+    pub(crate) fn edition(&self) -> Option<&str> {
+        self.opts.edition.as_ref().and_then(|e| e.to_str())
+    }
+
+    pub(crate) fn env(&self, key: &str) -> Option<OsString> {
+        self.cmd.get_env(key)
+    }
+
     pub(crate) fn capture_error_messages(
         &mut self,
         exclude: &FixedBitSet,
@@ -95,6 +125,117 @@ impl<'a> Rustc<'a> {
             .map(|_| ())
     }
 
+    /// Compiles with all of the externs and collects the ones reported by the
+    /// `unused_crate_dependencies` lint.
+    ///
+    /// Returns `None` if the lint is unknown to rustc or the compilation fails.
+    pub(crate) fn capture_unused_externs(
+        &mut self,
+        on_stdout_line: &mut dyn FnMut(&str) -> CargoResult<()>,
+        on_stderr_line: &mut dyn FnMut(&str) -> CargoResult<()>,
+    ) -> CargoResult<Option<FixedBitSet>> {
+        let no_exclusion = FixedBitSet::with_capacity(self.opts.r#extern.len());
+        let (args, lints_capped) =
+            warn_unused_crate_dependencies(self.opts.to_args(&no_exclusion, true));
+        self.cmd.args_replace(&args);
+
+        let mut lines = vec![];
+        let result = self.cmd.exec_with_streaming(
+            on_stdout_line,
+            &mut |line| {
+                lines.push(line.to_owned());
+                Ok(())
+            },
+            true,
+        );
+        if let Err(err) = result {
+            if err
+                .iter_chain()
+                .all(|e| e.downcast_ref::<ProcessError>().is_none())
+            {
+                return Err(err);
+            }
+            on_stderr_line(&format!(
+                "Could not compile {} with all of the externs",
+                self.target.description_named(),
+            ))?;
+            return Ok(None);
+        }
+
+        let mut messages = vec![];
+        for line in lines {
+            match serde_json::from_str::<ErrorMessage>(&line) {
+                Ok(message) if message.is_about_unused_crate_dependencies() => {
+                    messages.push(message)
+                }
+                // The other diagnostics are hidden only if cargo would hide them.
+                _ if !lints_capped => on_stderr_line(&line)?,
+                _ => {}
+            }
+        }
+        let unused = match unused_externs(&messages, self.externs()) {
+            None => return Ok(None),
+            Some(unused) => unused,
+        };
+        self.eprint_exclusion(&unused, on_stderr_line)?;
+        Ok(Some(unused))
+    }
+
+    /// Runs rustc with `--emit=dep-info` and returns the source files it read.
+    ///
+    /// Returns `None` if rustc fails.
+    pub(crate) fn capture_dep_info(
+        &mut self,
+        on_stdout_line: &mut dyn FnMut(&str) -> CargoResult<()>,
+    ) -> CargoResult<Option<Vec<PathBuf>>> {
+        let out_dir = TempDir::new("cargo_linked")?;
+        let no_exclusion = FixedBitSet::with_capacity(self.opts.r#extern.len());
+        let mut args = self.opts.to_args(&no_exclusion, true);
+        let replacements: [(&str, &OsStr); 2] = [
+            ("--emit", "dep-info".as_ref()),
+            ("--out-dir", out_dir.path().as_ref()),
+        ];
+        for &(flag, value) in &replacements {
+            if let Some(i) = args.iter().position(|&a| a == flag) {
+                args.drain(i..i + 2);
+            }
+            args.insert(0, flag.as_ref());
+            args.insert(1, value);
+        }
+        if let Some(i) = args.iter().position(|&a| a == "-o") {
+            args.drain(i..i + 2);
+        }
+        self.cmd.args_replace(&args);
+
+        if self
+            .cmd
+            .exec_with_streaming(on_stdout_line, &mut |_| Ok(()), true)
+            .is_err()
+        {
+            return Ok(None);
+        }
+
+        let dep_info = std::fs::read_dir(out_dir.path())?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|e| e.path())
+            .find(|p| p.extension() == Some("d".as_ref()));
+        let dep_info = match dep_info {
+            None => return Ok(None),
+            Some(dep_info) => std::fs::read_to_string(dep_info)?,
+        };
+        let cwd = match self.cmd.get_cwd() {
+            Some(cwd) => cwd.to_owned(),
+            None => std::env::current_dir()?,
+        };
+        Ok(Some(
+            parse_dep_info(&dep_info)
+                .into_iter()
+                .map(|p| cwd.join(p))
+                .collect(),
+        ))
+    }
+
     fn eprint_exclusion(
         &self,
         exclude: &FixedBitSet,
@@ -124,6 +265,101 @@ impl<'a> Rustc<'a> {
     }
 }
 
+/// Whether the toolchain knows the `unused_crate_dependencies` lint and `--force-warn`.
+///
+/// The output is cached by cargo, so rustc is asked once per toolchain.
+pub(crate) fn supports_unused_crate_dependencies(rustc: &cargo::util::Rustc) -> bool {
+    let mut cmd = rustc.process_no_wrapper();
+    cmd.args(&["--force-warn", "unused-crate-dependencies", "-W", "help"]);
+    rustc
+        .cached_output(&cmd)
+        .map(|(stdout, _)| stdout.contains("unused-crate-dependencies"))
+        .unwrap_or(false)
+}
+
+/// Makes rustc warn about the unused externs even if the crate `#![allow]`s them, replacing
+/// `--cap-lints` which cargo passes for the dependencies.
+///
+/// Also returns whether the lints were capped.
+fn warn_unused_crate_dependencies(mut args: Vec<&OsStr>) -> (Vec<&OsStr>, bool) {
+    let mut lints_capped = false;
+    if let Some(i) = args.iter().position(|&a| a == "--cap-lints") {
+        args.drain(i..i + 2);
+        lints_capped = true;
+    }
+    let input = args.pop();
+    args.extend(&[
+        "--cap-lints".as_ref(),
+        "warn".as_ref(),
+        "--force-warn".as_ref(),
+        "unused-crate-dependencies".as_ref(),
+    ]);
+    args.extend(input);
+    (args, lints_capped)
+}
+
+/// Reads the unused externs from the messages of the `unused_crate_dependencies` lint.
+///
+/// The lint is forced with `--force-warn`, so no message means every extern is used. Returns
+/// `None` if the lint is unknown to rustc.
+fn unused_externs(messages: &[ErrorMessage], externs: &[Extern]) -> Option<FixedBitSet> {
+    static UNUSED_EXTERN: Lazy<Regex> =
+        lazy_regex!(r"\Aextern(al)? crate `([a-zA-Z0-9_]+)` (is )?unused in ");
+
+    let mut unused = FixedBitSet::with_capacity(externs.len());
+    for message in messages {
+        match message.code.as_ref().map(|c| &*c.code) {
+            Some("unused_crate_dependencies") => {
+                if let Some(caps) = UNUSED_EXTERN.captures(&message.message) {
+                    if let Some(pos) = externs.iter().position(|e| e.name() == &caps[2]) {
+                        unused.insert(pos);
+                    }
+                }
+            }
+            _ if message.is_about_unused_crate_dependencies() => return None,
+            _ => {}
+        }
+    }
+    Some(unused)
+}
+
+/// Parses the first rule of a dep-info, which is `<output>: <input>...`.
+fn parse_dep_info(dep_info: &str) -> Vec<PathBuf> {
+    let rule = match dep_info
+        .lines()
+        .find(|l| !(l.is_empty() || l.starts_with('#')))
+    {
+        None => return vec![],
+        Some(rule) => rule,
+    };
+    let inputs = match rule.find(": ") {
+        None => return vec![],
+        Some(i) => &rule[i + 2..],
+    };
+    let (mut paths, mut path) = (vec![], "".to_owned());
+    let mut chars = inputs.chars();
+    while let Some(c) = chars.next() {
+        // rustc escapes only the spaces, so the other backslashes are separators on Windows.
+        match c {
+            '\\' if chars.as_str().starts_with(' ') => {
+                path.push(' ');
+                chars.next();
+            }
+            '\\' if chars.as_str().starts_with("\\ ") => {
+                path.push_str("\\ ");
+                chars.nth(1);
+            }
+            ' ' if !path.is_empty() => paths.push(PathBuf::from(mem::take(&mut path))),
+            ' ' => {}
+            c => path.push(c),
+        }
+    }
+    if !path.is_empty() {
+        paths.push(path.into());
+    }
+    paths
+}
+
 #[derive(Debug, StructOpt)]
 pub(crate) struct RustcOpts {
     #[structopt(long, parse(from_os_str))]
@@ -197,6 +433,45 @@ impl RustcOpts {
         self.crate_name.as_deref()
     }
 
+    /// Returns the `cfg`s of the unit, starting from the ones of the platform it is compiled for.
+    fn cfgs(&self, host: &[Cfg], target: &[Cfg]) -> Fallible<Vec<Cfg>> {
+        let codegen = |key: &str| {
+            self.codegen
+                .iter()
+                .flat_map(|c| c.to_str())
+                .map(|c| c.splitn(2, '='))
+                .flat_map(|mut kv| match (kv.next(), kv.next()) {
+                    (Some(k), v) if k == key => Some(v.unwrap_or("")),
+                    _ => None,
+                })
+                .last()
+        };
+
+        let mut cfgs = if self.target.is_some() { target } else { host }.to_vec();
+
+        // `rustc --print cfg` is run without the `-C`s of the profile.
+        let debug_assertions = Cfg::Name("debug_assertions".to_owned());
+        cfgs.retain(|cfg| *cfg != debug_assertions);
+        let enabled = match codegen("debug-assertions") {
+            Some(value) => ["", "y", "yes", "on"].contains(&value),
+            None => !self.opt_level_2 && codegen("opt-level").unwrap_or("0") == "0",
+        };
+        if enabled {
+            cfgs.push(debug_assertions);
+        }
+
+        if self.test {
+            cfgs.push(Cfg::Name("test".to_owned()));
+        }
+        for cfg in &self.cfg {
+            let cfg = cfg
+                .to_str()
+                .ok_or_else(|| failure::err_msg(format!("Invalid UTF-8: {:?}", cfg)))?;
+            cfgs.push(cfg.parse()?);
+        }
+        Ok(cfgs)
+    }
+
     #[allow(clippy::cognitive_complexity)]
     fn to_args(&self, exclude: &FixedBitSet, error_format_json: bool) -> Vec<&OsStr> {
         let mut args = Vec::<&OsStr>::new();
@@ -356,3 +631,117 @@ impl AsRef<OsStr> for Extern {
         self.string.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorMessage, Extern};
+
+    use failure::Fallible;
+
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_warn_unused_crate_dependencies() {
+        let (args, lints_capped) = super::warn_unused_crate_dependencies(
+            ["--crate-name", "foo", "--cap-lints", "allow", "src/lib.rs"]
+                .iter()
+                .map(OsStr::new)
+                .collect(),
+        );
+        assert_eq!(
+            args,
+            [
+                "--crate-name",
+                "foo",
+                "--cap-lints",
+                "warn",
+                "--force-warn",
+                "unused-crate-dependencies",
+                "src/lib.rs",
+            ],
+        );
+        assert!(lints_capped);
+
+        let (args, lints_capped) =
+            super::warn_unused_crate_dependencies(vec![OsStr::new("src/main.rs")]);
+        assert_eq!(args.last(), Some(&OsStr::new("src/main.rs")));
+        assert!(!lints_capped);
+    }
+
+    #[test]
+    fn test_unused_externs() -> Fallible<()> {
+        let externs = ["log=/deps/liblog.rlib", "serde=/deps/libserde.rlib"]
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Fallible<Vec<Extern>>>()?;
+        let message = |code: &str, message: &str| -> ErrorMessage {
+            serde_json::from_value(serde_json::json!({
+                "message": message,
+                "code": { "code": code },
+            }))
+            .unwrap()
+        };
+
+        let unused = super::unused_externs(
+            &[message(
+                "unused_crate_dependencies",
+                "external crate `serde` unused in `foo`: remove the dependency or add `use serde as _;`",
+            )],
+            &externs,
+        )
+        .unwrap();
+        assert_eq!(unused.ones().collect::<Vec<_>>(), [1]);
+
+        let unknown = message("unknown_lints", "unknown lint: `unused_crate_dependencies`");
+        assert!(super::unused_externs(&[unknown], &externs).is_none());
+        let unknown = message("E0602", "unknown lint: `unused-crate-dependencies`");
+        assert!(super::unused_externs(&[unknown], &externs).is_none());
+
+        // Every extern is used.
+        let unused = super::unused_externs(&[], &externs).unwrap();
+        assert_eq!(unused.len(), externs.len());
+        assert_eq!(unused.count_ones(..), 0);
+        assert_eq!(super::unused_externs(&[], &[]).unwrap().len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_dep_info() {
+        static DEP_INFO: &str = r"/target/debug/deps/foo-0123456789abcdef.d: src/lib.rs src/a\ b.rs /target/debug/build/foo-0123456789abcdef/out/generated.rs
+
+src/lib.rs:
+src/a\ b.rs:
+/target/debug/build/foo-0123456789abcdef/out/generated.rs:
+
+# env-dep:OUT_DIR=/target/debug/build/foo-0123456789abcdef/out
+";
+
+        assert_eq!(
+            super::parse_dep_info(DEP_INFO),
+            [
+                "src/lib.rs",
+                "src/a b.rs",
+                "/target/debug/build/foo-0123456789abcdef/out/generated.rs",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>(),
+        );
+
+        static WINDOWS_DEP_INFO: &str = r"C:\target\debug\deps\foo-0123456789abcdef.d: src\lib.rs C:\a\ b.rs C:\c\\ d.rs
+
+src\lib.rs:
+C:\a\ b.rs:
+C:\c\\ d.rs:
+";
+
+        assert_eq!(
+            super::parse_dep_info(WINDOWS_DEP_INFO),
+            [r"src\lib.rs", r"C:\a b.rs", r"C:\c\ d.rs"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>(),
+        );
+    }
+}
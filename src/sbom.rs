@@ -0,0 +1,380 @@
+//! SBOMs of the packages linked into the artifacts.
+//!
+//! Only the packages used at runtime by the `lib`s and the `bin`s are listed. Proc-macros, build
+//! dependencies, and the dependencies of the tests, the examples and the benches do not end up in
+//! the artifacts, and neither do the unused packages.
+
+use crate::license;
+use crate::{LinkedOutcome, LinkedPackagesMetadata, UsedAs};
+
+use cargo::core::PackageId;
+use maplit::{btreemap, btreeset};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+use serde_json::{json, Value};
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// The characters percent-encoded in the qualifiers of the purls.
+const QUALIFIER: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/')
+    .remove(b':');
+
+/// Renders a CycloneDX 1.4 BOM.
+pub(crate) fn cyclonedx(outcome: &LinkedOutcome) -> Value {
+    let linked = Linked::new(outcome);
+    let components = linked
+        .packages()
+        .map(|id| {
+            let mut component = json!({
+                "type": if linked.members.contains(&id) { "application" } else { "library" },
+                "bom-ref": purl(id),
+                "name": id.name().as_str(),
+                "version": id.version().to_string(),
+                "purl": purl(id),
+            });
+            if let Some(license) = linked.license(id) {
+                component["licenses"] = json!([{ "expression": license }]);
+            }
+            if let Some(checksum) = linked.metadata.checksums.get(&id) {
+                component["hashes"] = json!([{ "alg": "SHA-256", "content": checksum }]);
+            }
+            component
+        })
+        .collect::<Vec<_>>();
+    let dependencies = linked
+        .packages()
+        .map(|id| {
+            let depends_on = linked.edges.get(&id).into_iter().flatten();
+            json!({
+                "ref": purl(id),
+                "dependsOn": depends_on.map(|&to| purl(to)).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.4",
+        "version": 1,
+        "metadata": {
+            "tools": [{ "name": "cargo-linked", "version": env!("CARGO_PKG_VERSION") }],
+        },
+        "components": components,
+        "dependencies": dependencies,
+    })
+}
+
+/// Renders an SPDX 2.3 document. `created` is an RFC 3339 timestamp.
+pub(crate) fn spdx(outcome: &LinkedOutcome, created: &str) -> Value {
+    let linked = Linked::new(outcome);
+
+    let mut spdx_ids = btreemap!();
+    let mut taken = HashSet::new();
+    for id in linked.packages() {
+        let base = format!("SPDXRef-Package-{}-{}", id.name(), id.version()).replace(
+            |c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'),
+            "-",
+        );
+        let mut spdx_id = base.clone();
+        let mut n = 1;
+        while !taken.insert(spdx_id.clone()) {
+            n += 1;
+            spdx_id = format!("{}-{}", base, n);
+        }
+        spdx_ids.insert(id, spdx_id);
+    }
+
+    let packages = linked
+        .packages()
+        .map(|id| {
+            let source_id = id.source_id();
+            let download_location = if source_id.is_default_registry() {
+                format!(
+                    "https://crates.io/api/v1/crates/{}/{}/download",
+                    id.name(),
+                    id.version(),
+                )
+            } else if source_id.is_git() {
+                format!("git+{}", source_id.url())
+            } else {
+                "NOASSERTION".to_owned()
+            };
+            let mut package = json!({
+                "SPDXID": spdx_ids[&id],
+                "name": id.name().as_str(),
+                "versionInfo": id.version().to_string(),
+                "downloadLocation": download_location,
+                "filesAnalyzed": false,
+                "licenseConcluded": "NOASSERTION",
+                "licenseDeclared": linked.license(id).unwrap_or_else(|| "NOASSERTION".to_owned()),
+                "copyrightText": "NOASSERTION",
+                "externalRefs": [{
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": purl(id),
+                }],
+            });
+            if let Some(checksum) = linked.metadata.checksums.get(&id) {
+                package["checksums"] =
+                    json!([{ "algorithm": "SHA256", "checksumValue": checksum }]);
+            }
+            package
+        })
+        .collect::<Vec<_>>();
+
+    let mut relationships = linked
+        .members
+        .iter()
+        .map(|member| {
+            json!({
+                "spdxElementId": "SPDXRef-DOCUMENT",
+                "relationshipType": "DESCRIBES",
+                "relatedSpdxElement": spdx_ids[member],
+            })
+        })
+        .collect::<Vec<_>>();
+    for (from, tos) in &linked.edges {
+        for to in tos {
+            relationships.push(json!({
+                "spdxElementId": spdx_ids[from],
+                "relationshipType": "DEPENDS_ON",
+                "relatedSpdxElement": spdx_ids[to],
+            }));
+        }
+    }
+
+    let name = linked
+        .members
+        .iter()
+        .map(|m| format!("{}-{}", m.name(), m.version()))
+        .collect::<Vec<_>>()
+        .join("+");
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": name,
+        "documentNamespace": format!(
+            "https://spdx.org/spdxdocs/cargo-linked/{}-{}",
+            percent_encoding::utf8_percent_encode(&name, QUALIFIER),
+            created,
+        ),
+        "creationInfo": {
+            "created": created,
+            "creators": [format!("Tool: cargo-linked-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+/// The members and the packages linked into them, merged across the platforms.
+struct Linked<'a> {
+    members: BTreeSet<PackageId>,
+    runtime: BTreeSet<PackageId>,
+    /// The used edges between `members` and `runtime`.
+    edges: BTreeMap<PackageId, BTreeSet<PackageId>>,
+    metadata: &'a LinkedPackagesMetadata,
+}
+
+impl<'a> Linked<'a> {
+    fn new(outcome: &'a LinkedOutcome) -> Self {
+        let mut linked = Self {
+            members: outcome
+                .platforms
+                .values()
+                .flat_map(|p| p.members.keys().cloned())
+                .collect(),
+            runtime: btreeset!(),
+            edges: btreemap!(),
+            metadata: &outcome.metadata,
+        };
+        for platform in outcome.platforms.values() {
+            for (&member, packages) in &platform.members {
+                let outcomes = if packages.targets.is_empty() {
+                    vec![packages]
+                } else {
+                    packages
+                        .targets
+                        .iter()
+                        .filter(|(kind, _)| ["lib", "bin"].contains(&&***kind))
+                        .flat_map(|(_, t)| t.values())
+                        .collect()
+                };
+                for target in outcomes {
+                    let runtime = |id: PackageId| {
+                        id != member && target.used_as.get(&id) == Some(&UsedAs::Runtime)
+                    };
+                    linked
+                        .runtime
+                        .extend(target.used.iter().cloned().filter(|&id| runtime(id)));
+                    for (&from, edges) in &target.resolved_edges {
+                        if !(from == member || runtime(from)) {
+                            continue;
+                        }
+                        for (&to, &used) in edges {
+                            if used && runtime(to) {
+                                linked.edges.entry(from).or_default().insert(to);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        linked
+    }
+
+    fn packages<'b>(&'b self) -> impl Iterator<Item = PackageId> + 'b {
+        (&self.members | &self.runtime).into_iter()
+    }
+
+    fn license(&self, id: PackageId) -> Option<String> {
+        self.metadata
+            .licenses
+            .get(&id)
+            .map(|l| license::expression(l))
+    }
+}
+
+/// `pkg:cargo/NAME@VERSION`, qualified with the source unless it is crates.io.
+fn purl(id: PackageId) -> String {
+    let mut ret = format!("pkg:cargo/{}@{}", id.name(), id.version());
+    let source_id = id.source_id();
+    let qualifier = if source_id.is_default_registry() {
+        None
+    } else if source_id.is_registry() {
+        Some(("repository_url", source_id.url().to_string()))
+    } else if source_id.is_git() {
+        let mut url = format!("git+{}", source_id.url());
+        if let Some(precise) = source_id.precise() {
+            url += &format!("@{}", precise);
+        }
+        Some(("vcs_url", url))
+    } else {
+        Some(("download_url", source_id.url().to_string()))
+    };
+    if let Some((key, value)) = qualifier {
+        ret += &format!(
+            "?{}={}",
+            key,
+            percent_encoding::utf8_percent_encode(&value, QUALIFIER),
+        );
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{crates_io_id, outcome_for, versioned_id};
+    use crate::{
+        LinkedOutcome, LinkedPackages, LinkedPackagesMetadata, LinkedPackagesUnused, UsedAs,
+    };
+
+    use maplit::{btreemap, btreeset};
+    use serde_json::json;
+
+    /// The `lib` of `foo` uses `serde` at runtime and `serde_derive` as a proc-macro, but not
+    /// `libc`. Only the tests use `tempdir`.
+    fn outcome() -> LinkedOutcome {
+        let foo = versioned_id("foo", "0.1.0");
+        let (serde, serde_derive, libc, tempdir) = (
+            crates_io_id("serde", "1.0.104"),
+            crates_io_id("serde_derive", "1.0.104"),
+            crates_io_id("libc", "0.2.66"),
+            crates_io_id("tempdir", "0.3.7"),
+        );
+
+        let lib = LinkedPackages {
+            used: btreeset!(serde, serde_derive),
+            used_as: btreemap!(serde => UsedAs::Runtime, serde_derive => UsedAs::ProcMacro),
+            unused: LinkedPackagesUnused {
+                trivial: btreeset!(),
+                maybe_obsolete: btreeset!(libc),
+            },
+            resolved_edges: btreemap!(
+                foo => btreemap!(serde => true, libc => false),
+                serde => btreemap!(serde_derive => true),
+            ),
+            ..Default::default()
+        };
+        let test = LinkedPackages {
+            used: btreeset!(serde, tempdir),
+            used_as: btreemap!(serde => UsedAs::Runtime, tempdir => UsedAs::Runtime),
+            resolved_edges: btreemap!(foo => btreemap!(serde => true, tempdir => true)),
+            ..Default::default()
+        };
+        let linked = LinkedPackages {
+            targets: btreemap!(
+                "lib".to_owned() => btreemap!("foo".to_owned() => lib),
+                "test".to_owned() => btreemap!("foo".to_owned() => test),
+            ),
+            ..Default::default()
+        };
+        let mut outcome = outcome_for(btreemap!(foo => linked));
+        outcome.metadata = LinkedPackagesMetadata {
+            licenses: btreemap!(serde => "MIT/Apache-2.0".to_owned()),
+            checksums: btreemap!(serde => "414115f2".to_owned()),
+            ..Default::default()
+        };
+        outcome
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let bom = super::cyclonedx(&outcome());
+        assert_eq!(bom["components"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            bom["components"][1],
+            json!({
+                "type": "library",
+                "bom-ref": "pkg:cargo/serde@1.0.104",
+                "name": "serde",
+                "version": "1.0.104",
+                "purl": "pkg:cargo/serde@1.0.104",
+                "licenses": [{ "expression": "MIT OR Apache-2.0" }],
+                "hashes": [{ "alg": "SHA-256", "content": "414115f2" }],
+            }),
+        );
+        assert_eq!(
+            bom["components"][0]["purl"],
+            "pkg:cargo/foo@0.1.0?download_url=file:///foo",
+        );
+        assert_eq!(
+            bom["dependencies"],
+            json!([
+                {
+                    "ref": "pkg:cargo/foo@0.1.0?download_url=file:///foo",
+                    "dependsOn": ["pkg:cargo/serde@1.0.104"],
+                },
+                { "ref": "pkg:cargo/serde@1.0.104", "dependsOn": [] },
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_spdx() {
+        let doc = super::spdx(&outcome(), "2020-01-01T00:00:00Z");
+        let package = &doc["packages"][1];
+        assert_eq!(package["SPDXID"], "SPDXRef-Package-serde-1.0.104");
+        assert_eq!(package["licenseDeclared"], "MIT OR Apache-2.0");
+        assert_eq!(package["checksums"][0]["checksumValue"], "414115f2");
+        assert_eq!(
+            doc["relationships"],
+            json!([
+                {
+                    "spdxElementId": "SPDXRef-DOCUMENT",
+                    "relationshipType": "DESCRIBES",
+                    "relatedSpdxElement": "SPDXRef-Package-foo-0.1.0",
+                },
+                {
+                    "spdxElementId": "SPDXRef-Package-foo-0.1.0",
+                    "relationshipType": "DEPENDS_ON",
+                    "relatedSpdxElement": "SPDXRef-Package-serde-1.0.104",
+                },
+            ]),
+        );
+    }
+}
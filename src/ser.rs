@@ -1,268 +1,271 @@
-use cargo::core::PackageId;
-use miniserde::ser::Fragment;
+//! The JSON output.
+//!
+//! Bump [`FORMAT_VERSION`] on any incompatible change.
 
-use std::borrow::{Borrow, Cow};
-use std::collections::BTreeMap;
-use std::fmt::Debug;
-use std::slice;
+use crate::{LinkedDirectDependency, LinkedOutcome, LinkedPackages, UsedAs};
 
-impl miniserde::Serialize for crate::Cache {
-    fn begin(&self) -> Fragment {
-        self.0.begin()
-    }
-}
+use cargo::core::{dependency, PackageId};
+use serde::Serialize;
 
-impl miniserde::Serialize for crate::CacheValue {
-    fn begin(&self) -> Fragment {
-        struct Map<V1, V2> {
-            key: V1,
-            used_packages: V2,
-            pos: usize,
-        }
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
 
-        impl<V1: miniserde::Serialize, V2: miniserde::Serialize> miniserde::ser::Map for Map<V1, V2> {
-            fn next(&mut self) -> Option<(Cow<str>, &dyn miniserde::Serialize)> {
-                match self.pos {
-                    0 => {
-                        self.pos = 1;
-                        Some(("key".into(), &self.key))
-                    }
-                    1 => {
-                        self.pos = 2;
-                        Some(("used_packages".into(), &self.used_packages))
-                    }
-                    _ => None,
-                }
-            }
-        }
+pub const FORMAT_VERSION: u32 = 1;
 
-        Fragment::Map(Box::new(Map {
-            key: &self.key,
-            used_packages: self
-                .used_packages
-                .iter()
-                .map(|(k, v)| (unwrap_to_string_with_serde(k), v))
-                .collect::<BTreeMap<_, _>>(),
-            pos: 0,
-        }))
-    }
+#[derive(Debug, Serialize)]
+pub struct Outcome {
+    pub format_version: u32,
+    /// Keyed by the target triples.
+    pub platforms: BTreeMap<String, Workspace>,
 }
 
-impl miniserde::Serialize for crate::CacheUsedPackages {
-    fn begin(&self) -> Fragment {
-        struct Map<V1, V2> {
-            lib: V1,
-            bin: V2,
-            test: V2,
-            bench: V2,
-            example_lib: V2,
-            example_bin: V2,
-            custom_build: V1,
-            pos: usize,
-        }
-
-        impl<V1: miniserde::Serialize, V2: miniserde::Serialize> miniserde::ser::Map for Map<V1, V2> {
-            fn next(&mut self) -> Option<(Cow<str>, &dyn miniserde::Serialize)> {
-                match self.pos {
-                    0 => {
-                        self.pos = 1;
-                        Some(("lib".into(), &self.lib))
-                    }
-                    1 => {
-                        self.pos = 2;
-                        Some(("bin".into(), &self.bin))
-                    }
-                    2 => {
-                        self.pos = 3;
-                        Some(("test".into(), &self.test))
-                    }
-                    3 => {
-                        self.pos = 4;
-                        Some(("bench".into(), &self.bench))
-                    }
-                    4 => {
-                        self.pos = 5;
-                        Some(("example_lib".into(), &self.example_lib))
-                    }
-                    5 => {
-                        self.pos = 6;
-                        Some(("example_bin".into(), &self.example_bin))
-                    }
-                    6 => {
-                        self.pos = 7;
-                        Some(("custom_build".into(), &self.custom_build))
-                    }
-                    _ => None,
-                }
-            }
-        }
-
-        Fragment::Map(Box::new(Map {
-            lib: miniser_package_ids_option(self.lib.as_ref()),
-            bin: miniser_to_string_package_ids_map(&self.bin),
-            test: miniser_to_string_package_ids_map(&self.test),
-            bench: miniser_to_string_package_ids_map(&self.bench),
-            example_lib: miniser_to_string_package_ids_map(&self.example_lib),
-            example_bin: miniser_to_string_package_ids_map(&self.example_bin),
-            custom_build: miniser_package_ids_option(self.custom_build.as_ref()),
-            pos: 0,
-        }))
-    }
+#[derive(Debug, Serialize)]
+pub struct Workspace {
+    pub members: Vec<Member>,
+    pub union: Packages,
 }
 
-impl miniserde::Serialize for crate::LinkedPackages {
-    fn begin(&self) -> Fragment {
-        struct Map<V1, V2> {
-            used: V1,
-            unused: V2,
-            pos: usize,
-        }
+#[derive(Debug, Serialize)]
+pub struct Member {
+    pub package: Package,
+    #[serde(flatten)]
+    pub outcome: Packages,
+}
 
-        impl<V1: miniserde::Serialize, V2: miniserde::Serialize> miniserde::ser::Map for Map<V1, V2> {
-            fn next(&mut self) -> Option<(Cow<str>, &dyn miniserde::Serialize)> {
-                match self.pos {
-                    0 => {
-                        self.pos = 1;
-                        Some(("used".into(), &self.used))
-                    }
-                    1 => {
-                        self.pos = 2;
-                        Some(("unused".into(), &self.unused))
-                    }
-                    _ => None,
-                }
-            }
-        }
+#[derive(Debug, Serialize)]
+pub struct Packages {
+    pub used: Vec<Package>,
+    pub unused: Unused,
+    /// Excluded from `used` and `unused` by `[package.metadata.cargo-linked]`.
+    pub ignored: Vec<Package>,
+    /// Keyed by the kind and the name. Only with `--all-targets`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub targets: Option<BTreeMap<String, BTreeMap<String, Packages>>>,
+    pub direct: Vec<DirectDependency>,
+}
 
-        Fragment::Map(Box::new(Map {
-            used: miniser_package_ids(&self.used),
-            unused: &self.unused,
-            pos: 0,
-        }))
-    }
+#[derive(Debug, Serialize)]
+pub struct Unused {
+    pub trivial: Vec<Package>,
+    pub maybe_obsolete: Vec<Package>,
 }
 
-impl miniserde::Serialize for crate::LinkedPackagesUnused {
-    fn begin(&self) -> Fragment {
-        struct Map<V> {
-            trivial: V,
-            maybe_obsolete: V,
-            pos: usize,
-        }
+#[derive(Debug, Serialize)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub source: Source,
+    /// The names the package is referred to as by the packages depending on it.
+    pub extern_crate_names: Vec<String>,
+    /// The kinds of the dependencies on the package.
+    pub dependency_kinds: Vec<DependencyKind>,
+    /// Only for the used packages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used_as: Option<UsedAs>,
+    /// The bytes of the code and the data in the artifact. Only with `--artifact`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+}
 
-        impl<V: miniserde::Serialize> miniserde::ser::Map for Map<V> {
-            fn next(&mut self) -> Option<(Cow<str>, &dyn miniserde::Serialize)> {
-                match self.pos {
-                    0 => {
-                        self.pos = 1;
-                        Some(("trivial".into(), &self.trivial))
-                    }
-                    1 => {
-                        self.pos = 2;
-                        Some(("maybe_obsolete".into(), &self.maybe_obsolete))
-                    }
-                    _ => None,
-                }
-            }
-        }
+#[derive(Debug, Serialize)]
+pub struct Source {
+    pub kind: SourceKind,
+    pub url: String,
+}
 
-        Fragment::Map(Box::new(Map {
-            trivial: miniser_package_ids(&self.trivial),
-            maybe_obsolete: miniser_package_ids(&self.maybe_obsolete),
-            pos: 0,
-        }))
-    }
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceKind {
+    Registry,
+    LocalRegistry,
+    Git,
+    Path,
+    Directory,
 }
 
-fn miniser_package_ids<P: Borrow<PackageId>, I: IntoIterator<Item = P>>(
-    ids: I,
-) -> impl miniserde::Serialize + 'static {
-    struct Serializer(Vec<String>);
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependencyKind {
+    Normal,
+    Dev,
+    Build,
+}
 
-    impl miniserde::Serialize for Serializer {
-        fn begin(&self) -> Fragment {
-            Fragment::Seq(Box::new(Seq(self.0.iter())))
+impl From<dependency::Kind> for DependencyKind {
+    fn from(kind: dependency::Kind) -> Self {
+        match kind {
+            dependency::Kind::Normal => DependencyKind::Normal,
+            dependency::Kind::Development => DependencyKind::Dev,
+            dependency::Kind::Build => DependencyKind::Build,
         }
     }
+}
 
-    struct Seq<'a>(slice::Iter<'a, String>);
+#[derive(Debug, Serialize)]
+pub struct DirectDependency {
+    pub name_in_toml: String,
+    pub package: Package,
+    pub kind: DependencyKind,
+    pub platform: Option<String>,
+    pub manifest_path: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub active: bool,
+    pub used: bool,
+}
 
-    impl<'a> miniserde::ser::Seq for Seq<'a> {
-        fn next(&mut self) -> Option<&dyn miniserde::Serialize> {
-            self.0.next().map(|s| s as &dyn miniserde::Serialize)
+impl From<&'_ LinkedOutcome> for Outcome {
+    fn from(outcome: &LinkedOutcome) -> Self {
+        let platforms = outcome
+            .platforms
+            .iter()
+            .map(|(triple, workspace)| {
+                let members = workspace
+                    .members
+                    .iter()
+                    .map(|(&id, linked)| Member {
+                        package: package(linked, id),
+                        outcome: packages(linked),
+                    })
+                    .collect();
+                let union = packages(&workspace.union);
+                (triple.clone(), Workspace { members, union })
+            })
+            .collect();
+        Self {
+            format_version: FORMAT_VERSION,
+            platforms,
         }
     }
+}
 
-    Serializer(
-        ids.into_iter()
-            .map(|id| unwrap_to_string_with_serde(id.borrow()))
+fn packages(linked: &LinkedPackages) -> Packages {
+    let package_list = |ids: &BTreeSet<_>| ids.iter().map(|&id| package(linked, id)).collect();
+    let targets = Some(&linked.targets)
+        .filter(|t| !t.is_empty())
+        .map(|targets| {
+            targets
+                .iter()
+                .map(|(kind, targets)| {
+                    let targets = targets
+                        .iter()
+                        .map(|(name, linked)| (name.clone(), packages(linked)))
+                        .collect();
+                    (kind.clone(), targets)
+                })
+                .collect()
+        });
+    Packages {
+        used: package_list(&linked.used),
+        unused: Unused {
+            trivial: package_list(&linked.unused.trivial),
+            maybe_obsolete: package_list(&linked.unused.maybe_obsolete),
+        },
+        ignored: package_list(&linked.ignored),
+        targets,
+        direct: linked
+            .direct
+            .iter()
+            .map(|d| direct_dependency(linked, d))
             .collect(),
-    )
+    }
 }
 
-fn miniser_package_ids_option<I: IntoIterator<Item = P>, P: Borrow<PackageId> + Debug>(
-    package_ids: Option<I>,
-) -> impl miniserde::Serialize {
-    package_ids.map(|ids| {
-        ids.into_iter()
-            .map(|id| unwrap_to_string_with_serde(id.borrow()))
-            .collect::<Vec<_>>()
-    })
+fn package(linked: &LinkedPackages, id: PackageId) -> Package {
+    let source_id = id.source_id();
+    let kind = if source_id.is_remote_registry() {
+        SourceKind::Registry
+    } else if source_id.is_registry() {
+        SourceKind::LocalRegistry
+    } else if source_id.is_git() {
+        SourceKind::Git
+    } else if source_id.is_path() {
+        SourceKind::Path
+    } else {
+        SourceKind::Directory
+    };
+    Package {
+        name: id.name().to_string(),
+        version: id.version().to_string(),
+        source: Source {
+            kind,
+            url: source_id.url().to_string(),
+        },
+        extern_crate_names: linked
+            .extern_crate_names
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+        dependency_kinds: linked
+            .dependency_kinds
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|&k| k.into())
+            .collect(),
+        used_as: linked.used_as.get(&id).cloned(),
+        size_bytes: linked.size_bytes.get(&id).cloned(),
+    }
 }
 
-fn miniser_to_string_package_ids_map<
-    M: IntoIterator<Item = (K, V)>,
-    K: ToString,
-    V: IntoIterator<Item = P>,
-    P: Borrow<PackageId>,
->(
-    map: M,
-) -> impl miniserde::Serialize {
-    struct Serializer<K>(Vec<(K, Vec<String>)>);
-
-    impl<K: ToString> miniserde::Serialize for Serializer<K> {
-        fn begin(&self) -> Fragment {
-            Fragment::Map(Box::new(Map(self.0.iter())))
-        }
+fn direct_dependency(linked: &LinkedPackages, entry: &LinkedDirectDependency) -> DirectDependency {
+    DirectDependency {
+        name_in_toml: entry.name_in_toml.clone(),
+        package: package(linked, entry.package),
+        kind: entry.kind.into(),
+        platform: entry.platform.clone(),
+        manifest_path: entry.manifest_path.clone(),
+        line: entry.line,
+        column: entry.column,
+        active: entry.active,
+        used: entry.used,
     }
+}
 
-    struct Map<'a, K>(slice::Iter<'a, (K, Vec<String>)>);
+#[cfg(test)]
+mod tests {
+    use crate::testing::{crates_io_id, outcome_for, versioned_id, PLATFORM};
+    use crate::{LinkedPackages, UsedAs};
 
-    impl<'a, K: ToString> miniserde::ser::Map for Map<'a, K> {
-        fn next(&mut self) -> Option<(Cow<str>, &dyn miniserde::Serialize)> {
-            self.0
-                .next()
-                .map(|(k, v)| (k.to_string().into(), v as &dyn miniserde::Serialize))
-        }
-    }
+    use cargo::core::dependency;
+    use maplit::{btreemap, btreeset};
+    use serde_json::json;
 
-    Serializer(
-        map.into_iter()
-            .map(|(key, val)| {
-                let val = val
-                    .into_iter()
-                    .map(|p| unwrap_to_string_with_serde(p.borrow()))
-                    .collect();
-                (key, val)
-            })
-            .collect(),
-    )
-}
+    #[test]
+    fn test_outcome() -> serde_json::Result<()> {
+        let foo = versioned_id("foo", "0.1.0");
+        let serde = crates_io_id("serde", "1.0.104");
 
-/// # Panics
-///
-/// Panics if `item` is not converted into `serde_json::Value::String`.
-fn unwrap_to_string_with_serde(item: impl Debug + serde::Serialize) -> String {
-    serde_json::to_value(&item)
-        .ok()
-        .and_then(|value| match value {
-            serde_json::Value::String(value) => Some(value),
-            _ => None,
-        })
-        .unwrap_or_else(|| {
-            panic!(
-                "{:?} cannot be converted into `serde_json::Value::String`",
-                item,
-            )
-        })
+        let linked = LinkedPackages {
+            used: btreeset!(serde),
+            used_as: btreemap!(serde => UsedAs::Runtime),
+            extern_crate_names: btreemap!(serde => btreeset!("serde".to_owned())),
+            dependency_kinds: btreemap!(serde => btreeset!(dependency::Kind::Normal)),
+            ..Default::default()
+        };
+        let outcome = outcome_for(btreemap!(foo => linked));
+        let outcome = serde_json::to_value(super::Outcome::from(&outcome))?;
+
+        let serde = json!({
+            "name": "serde",
+            "version": "1.0.104",
+            "source": {
+                "kind": "registry",
+                "url": "https://github.com/rust-lang/crates.io-index",
+            },
+            "extern_crate_names": ["serde"],
+            "dependency_kinds": ["normal"],
+            "used_as": "runtime",
+        });
+        assert_eq!(outcome["format_version"], 1);
+        let platform = &outcome["platforms"][PLATFORM];
+        assert_eq!(platform["members"][0]["package"]["name"], "foo");
+        assert_eq!(platform["members"][0]["package"]["source"]["kind"], "path");
+        assert_eq!(platform["members"][0]["used"], json!([serde]));
+        assert_eq!(platform["union"]["used"], json!([serde]));
+        assert!(platform["union"].get("targets").is_none());
+        Ok(())
+    }
 }
@@ -0,0 +1,204 @@
+//! Size attribution of the symbols in a built artifact.
+//!
+//! Only the Rust symbols in ELF files are attributed. Symbols of C libraries compiled by
+//! `-sys` crates cannot be traced back to the packages.
+
+use cargo::CargoResult;
+use failure::format_err;
+use maplit::btreemap;
+use object::{Object as _, ObjectSymbol as _, SymbolKind};
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+/// The crates whose generic items are attributed to the traits they implement instead.
+const STD_CRATES: &[&str] = &["alloc", "core", "std"];
+
+/// Sums up the bytes of the code and the data defined in the ELF file, by the crate names.
+pub(crate) fn sizes_by_crate(path: &Path) -> CargoResult<BTreeMap<String, u64>> {
+    let data = crate::fs::read(path)?;
+    let file = object::File::parse(&*data)
+        .map_err(|err| format_err!("Failed to parse {}: {}", path.display(), err))?;
+
+    let mut sizes = btreemap!();
+    let mut addresses = HashSet::new();
+    for symbol in file.symbols() {
+        if !(symbol.is_definition()
+            && [SymbolKind::Text, SymbolKind::Data].contains(&symbol.kind()))
+            || symbol.size() == 0
+            || !addresses.insert(symbol.address())
+        {
+            continue;
+        }
+        let name = match symbol.name() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let demangled = match rustc_demangle::try_demangle(name) {
+            Ok(demangled) => format!("{:#}", demangled),
+            Err(_) => continue,
+        };
+        if let Some(crate_name) = crate_name(&demangled) {
+            *sizes.entry(crate_name.to_owned()).or_insert(0) += symbol.size();
+        }
+    }
+    Ok(sizes)
+}
+
+/// The crate a demangled path comes from.
+///
+/// For `<A as B>::f`, the crate of `A` unless it is a standard library crate.
+fn crate_name(demangled: &str) -> Option<&str> {
+    if !demangled.starts_with('<') {
+        return path_crate_name(demangled);
+    }
+    let mut depth = 0;
+    let mut self_ty_end = None;
+    let mut trait_range = None;
+    for (i, c) in demangled.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    trait_range = self_ty_end.map(|end| end + 4..i);
+                    self_ty_end = self_ty_end.or(Some(i));
+                    break;
+                }
+            }
+            ' ' if depth == 1 && demangled[i..].starts_with(" as ") => self_ty_end = Some(i),
+            _ => {}
+        }
+    }
+    let self_ty = path_crate_name(&demangled[1..self_ty_end?]);
+    let trait_ = trait_range.and_then(|r| path_crate_name(&demangled[r]));
+    match (self_ty, trait_) {
+        (Some(self_ty), Some(trait_)) if STD_CRATES.contains(&self_ty) => Some(trait_),
+        (Some(self_ty), _) => Some(self_ty),
+        (None, trait_) => trait_,
+    }
+}
+
+/// The first segment of a path, following references, pointers, slices and tuples.
+fn path_crate_name(path: &str) -> Option<&str> {
+    let mut path = path;
+    loop {
+        let trimmed = ["&", "*const ", "*mut ", "mut ", "dyn ", "[", "("]
+            .iter()
+            .fold(path, |p, prefix| p.trim_start_matches(prefix));
+        if trimmed == path {
+            break;
+        }
+        path = trimmed;
+    }
+    let end = path.find("::")?;
+    let crate_name = &path[..end];
+    if crate_name.is_empty() || !crate_name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    Some(crate_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{outcome_for, versioned_id as id, PLATFORM};
+    use crate::{LinkedOutcome, LinkedPackages, LinkedPackagesMetadata, LinkedWorkspace};
+
+    use cargo::CargoResult;
+    use maplit::{btreemap, btreeset};
+
+    #[test]
+    fn test_crate_name() {
+        let crate_name = |mangled: &str| {
+            let demangled = format!("{:#}", rustc_demangle::demangle(mangled));
+            super::crate_name(&demangled).map(ToOwned::to_owned)
+        };
+        assert_eq!(
+            crate_name("_ZN4core3fmt5write17h0123456789abcdefE"),
+            Some("core".to_owned()),
+        );
+        assert_eq!(
+            super::crate_name("serde_json::to_string"),
+            Some("serde_json")
+        );
+        assert_eq!(
+            super::crate_name(
+                "<serde_json::ser::Serializer<W> as serde::Serializer>::serialize_str"
+            ),
+            Some("serde_json"),
+        );
+        assert_eq!(
+            super::crate_name("<alloc::vec::Vec<T> as serde::Serialize>::serialize"),
+            Some("serde"),
+        );
+        assert_eq!(
+            super::crate_name("<&mut regex::Regex as core::fmt::Debug>::fmt"),
+            Some("regex"),
+        );
+        assert_eq!(
+            super::crate_name("<[u8] as core::fmt::Debug>::fmt"),
+            Some("core")
+        );
+        assert_eq!(super::crate_name("<foo::Bar>::new"), Some("foo"));
+        assert_eq!(super::crate_name("main"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sizes_by_crate() -> CargoResult<()> {
+        let sizes = super::sizes_by_crate(&std::env::current_exe()?)?;
+        assert!(sizes["cargo_linked"] > 0);
+        assert!(sizes["std"] > 0);
+        Ok(())
+    }
+
+    /// `serde-json` has no `lib` name recorded, the `lib` of `foo` is named `bar`, and the two
+    /// `rand`s share the crate name.
+    #[test]
+    fn test_attribute_sizes() -> CargoResult<()> {
+        let linked = || LinkedPackages {
+            used: btreeset!(
+                id("root", "0.0.0"),
+                id("serde-json", "1.0.0"),
+                id("foo", "0.1.0"),
+                id("rand", "0.7.0"),
+                id("rand", "0.8.0"),
+            ),
+            ..Default::default()
+        };
+        let workspace = || LinkedWorkspace::new(btreemap!(id("root", "0.0.0") => linked()));
+        let sizes = btreemap!(
+            "bar".to_owned() => 20,
+            "foo".to_owned() => 99,
+            "rand".to_owned() => 30,
+            "root".to_owned() => 40,
+            "serde_json".to_owned() => 10,
+        );
+
+        let mut outcome = outcome_for(btreemap!(id("root", "0.0.0") => linked()));
+        outcome.metadata = LinkedPackagesMetadata {
+            lib_names: btreemap!(id("foo", "0.1.0") => "bar".to_owned()),
+            ..Default::default()
+        };
+        outcome.attribute_sizes(&sizes)?;
+        let expected = btreemap!(
+            id("root", "0.0.0") => 40,
+            id("serde-json", "1.0.0") => 10,
+            id("foo", "0.1.0") => 20,
+        );
+        let linux = &outcome.platforms[PLATFORM];
+        assert_eq!(linux.union.size_bytes, expected);
+        assert_eq!(linux.members[&id("root", "0.0.0")].size_bytes, expected);
+
+        let platforms = btreemap!(
+            PLATFORM.to_owned() => workspace(),
+            "x86_64-pc-windows-msvc".to_owned() => workspace(),
+        );
+        let mut outcome = LinkedOutcome {
+            platforms,
+            ..Default::default()
+        };
+        assert!(outcome.attribute_sizes(&sizes).is_err());
+        Ok(())
+    }
+}
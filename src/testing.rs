@@ -0,0 +1,38 @@
+//! Fixtures shared by the tests.
+
+use crate::{LinkedOutcome, LinkedPackages, LinkedWorkspace};
+
+use cargo::core::{PackageId, SourceId};
+use maplit::btreemap;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The platform of `outcome_for`.
+pub(crate) const PLATFORM: &str = "x86_64-unknown-linux-gnu";
+
+/// `<name> 0.0.0 (/<name>)`
+pub(crate) fn id(name: &str) -> PackageId {
+    versioned_id(name, "0.0.0")
+}
+
+/// `<name> <version> (/<name>)`
+pub(crate) fn versioned_id(name: &str, version: &str) -> PackageId {
+    let source_id = SourceId::for_path(&Path::new("/").join(name)).unwrap();
+    PackageId::new(name, version, source_id).unwrap()
+}
+
+/// `<name> <version>` from crates.io.
+pub(crate) fn crates_io_id(name: &str, version: &str) -> PackageId {
+    let source_id = SourceId::crates_io(&cargo::Config::default().unwrap()).unwrap();
+    PackageId::new(name, version, source_id).unwrap()
+}
+
+/// The outcome for `PLATFORM` only.
+pub(crate) fn outcome_for(members: BTreeMap<PackageId, LinkedPackages>) -> LinkedOutcome {
+    let platforms = btreemap!(PLATFORM.to_owned() => LinkedWorkspace::new(members));
+    LinkedOutcome {
+        platforms,
+        ..Default::default()
+    }
+}
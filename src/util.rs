@@ -1,6 +1,6 @@
 use cargo::core::compiler::CompileMode;
 use cargo::core::manifest::{Target, TargetKind};
-use cargo::core::Workspace;
+use cargo::core::{Package, PackageIdSpec, Workspace};
 use cargo::ops::CompileOptions;
 use cargo::util::command_prelude::{ArgMatchesExt, ProfileChecking};
 use cargo::{CargoResult, Config};
@@ -17,6 +17,7 @@ pub(crate) struct Configure<'a, F: FnOnce(PathBuf) -> PathBuf> {
     pub(crate) frozen: bool,
     pub(crate) locked: bool,
     pub(crate) offline: bool,
+    pub(crate) verbose: u32,
     pub(crate) modify_target_dir: F,
 }
 
@@ -28,6 +29,7 @@ impl<F: FnOnce(PathBuf) -> PathBuf> Configure<'_, F> {
             frozen,
             locked,
             offline,
+            verbose,
             modify_target_dir,
         } = self;
 
@@ -37,7 +39,7 @@ impl<F: FnOnce(PathBuf) -> PathBuf> Configure<'_, F> {
         let target_dir = modify_target_dir(target_dir);
 
         config.configure(
-            0,
+            verbose,
             None,
             color,
             frozen,
@@ -50,14 +52,17 @@ impl<F: FnOnce(PathBuf) -> PathBuf> Configure<'_, F> {
 }
 
 #[derive(Clone, Copy, Debug)]
-pub(crate) struct CompileOptionsForSingleTarget<'a, 'b> {
+pub(crate) struct CompileOptionsForTargets<'a, 'b> {
     pub(crate) ws: &'a Workspace<'a>,
+    pub(crate) package: &'a Package,
     pub(crate) jobs: &'b Option<String>,
+    pub(crate) all_targets: bool,
     pub(crate) lib: bool,
     pub(crate) bin: &'b Option<String>,
     pub(crate) example: &'b Option<String>,
     pub(crate) test: &'b Option<String>,
     pub(crate) bench: &'b Option<String>,
+    pub(crate) triple: Option<&'b str>,
     pub(crate) release: bool,
     pub(crate) features: &'b [String],
     pub(crate) all_features: bool,
@@ -66,18 +71,21 @@ pub(crate) struct CompileOptionsForSingleTarget<'a, 'b> {
     pub(crate) compile_mode: CompileMode,
 }
 
-impl<'a> CompileOptionsForSingleTarget<'a, '_> {
-    pub(crate) fn compile_options_for_single_target(
+impl<'a> CompileOptionsForTargets<'a, '_> {
+    pub(crate) fn compile_options_for_targets(
         self,
-    ) -> CargoResult<(CompileOptions<'a>, &'a Target)> {
+    ) -> CargoResult<(CompileOptions<'a>, Vec<&'a Target>)> {
         let Self {
             ws,
+            package,
             jobs,
+            all_targets,
             lib,
             bin,
             example,
             test,
             bench,
+            triple,
             release,
             features,
             all_features,
@@ -86,13 +94,19 @@ impl<'a> CompileOptionsForSingleTarget<'a, '_> {
             compile_mode,
         } = self;
 
-        let mut args = hashmap!("manifest-path" => vec![manifest_path.into()]);
+        let mut args = hashmap!(
+            "manifest-path" => vec![manifest_path.into()],
+            "package" => vec![PackageIdSpec::from_package_id(package.package_id()).to_string().into()],
+        );
         if let Some(jobs) = jobs {
             args.insert("jobs", vec![jobs.into()]);
         }
         if !features.is_empty() {
             args.insert("features", features.iter().map(Into::into).collect());
         }
+        if let Some(triple) = triple {
+            args.insert("target", vec![triple.into()]);
+        }
         if all_features {
             args.insert("all-features", vec![]);
         }
@@ -100,10 +114,8 @@ impl<'a> CompileOptionsForSingleTarget<'a, '_> {
             args.insert("no-default-features", vec![]);
         }
 
-        let current = ws.current()?;
-
         let find_by_name = |name: &str, kind: &'static str| -> _ {
-            current
+            package
                 .targets()
                 .iter()
                 .find(|t| t.name() == name && t.kind().description() == kind)
@@ -114,43 +126,57 @@ impl<'a> CompileOptionsForSingleTarget<'a, '_> {
             args.insert("release", vec![]);
         }
 
-        let (arg_key, arg_val, target) = if lib {
-            let target = current
+        let (arg_key, arg_val, targets) = if all_targets {
+            let targets = package
+                .targets()
+                .iter()
+                .filter(|t| !t.is_custom_build())
+                .collect();
+            ("all-targets", vec![], targets)
+        } else if lib {
+            let target = package
                 .targets()
                 .iter()
                 .find(|t| t.is_lib())
                 .ok_or_else(|| {
-                    failure::err_msg("Current workspace member does not contain `lib`")
+                    failure::err_msg(format!("`{}` does not contain `lib`", package.name()))
                 })?;
-            ("lib", vec![], target)
+            ("lib", vec![], vec![target])
         } else if let Some(bin) = bin {
             let target = find_by_name(bin, "bin")?;
-            ("bin", vec![OsString::from(bin)], target)
+            ("bin", vec![OsString::from(bin)], vec![target])
         } else if let Some(test) = test {
             let target = find_by_name(test, "integration-test")?;
-            ("test", vec![OsString::from(test)], target)
+            ("test", vec![OsString::from(test)], vec![target])
         } else if let Some(bench) = bench {
             let target = find_by_name(bench, "bench")?;
-            ("bench", vec![OsString::from(bench)], target)
+            ("bench", vec![OsString::from(bench)], vec![target])
         } else if let Some(example) = example {
             let target = find_by_name(example, "example")?;
-            ("example", vec![OsString::from(example)], target)
+            ("example", vec![OsString::from(example)], vec![target])
         } else {
-            let bins = current
+            let bins = package
                 .targets()
                 .iter()
                 .filter(|t| *t.kind() == TargetKind::Bin)
                 .collect::<Vec<_>>();
-            let target = if bins.len() == 1 {
-                &bins[0]
+            let lib = package.targets().iter().find(|t| t.is_lib());
+            if let (true, Some(lib)) = (bins.is_empty(), lib) {
+                ("lib", vec![], vec![lib])
             } else {
-                let name = current
-                    .manifest()
-                    .default_run()
-                    .ok_or_else(|| failure::err_msg("Could not determine which binary to run"))?;
-                find_by_name(name, "bin")?
-            };
-            ("bin", vec![OsString::from(target.name())], target)
+                let target = if bins.len() == 1 {
+                    &bins[0]
+                } else {
+                    let name = package.manifest().default_run().ok_or_else(|| {
+                        failure::err_msg(format!(
+                            "Could not determine which binary to run in `{}`",
+                            package.name(),
+                        ))
+                    })?;
+                    find_by_name(name, "bin")?
+                };
+                ("bin", vec![OsString::from(target.name())], vec![target])
+            }
         };
 
         args.insert(arg_key, arg_val);
@@ -161,7 +187,7 @@ impl<'a> CompileOptionsForSingleTarget<'a, '_> {
             Some(ws),
             ProfileChecking::Checked,
         )?;
-        Ok((compile_opts, target))
+        Ok((compile_opts, targets))
     }
 }
 
@@ -215,3 +241,138 @@ fn arg_matches_from(map: HashMap<&'static str, Vec<OsString>>) -> impl ArgMatche
 
     DummyArgMatches(map)
 }
+
+#[cfg(test)]
+mod tests {
+    use cargo::core::compiler::{CompileKind, CompileMode, CompileTarget};
+    use cargo::core::Workspace;
+    use cargo::ops::Packages;
+    use cargo::CargoResult;
+    use tempdir::TempDir;
+
+    use std::path::Path;
+
+    /// `a` has a `lib`, two `bin`s and a `test`, `b` has only a `lib`, and `c` has only a `bin`.
+    fn workspace() -> CargoResult<TempDir> {
+        let tempdir = TempDir::new("cargo-linked-test-util")?;
+        let write = |path: &str, content: &str| -> CargoResult<()> {
+            let path = tempdir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, content).map_err(Into::into)
+        };
+        write(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n",
+        )?;
+        for name in &["a", "b", "c"] {
+            let manifest = format!(
+                "[package]\nname = \"{}\"\nversion = \"0.0.0\"\nedition = \"2018\"\n",
+                name,
+            );
+            write(&format!("{}/Cargo.toml", name), &manifest)?;
+        }
+        for path in &[
+            "a/src/lib.rs",
+            "a/src/bin/x.rs",
+            "a/src/bin/y.rs",
+            "a/tests/t.rs",
+            "b/src/lib.rs",
+            "c/src/main.rs",
+        ] {
+            write(path, "")?;
+        }
+        Ok(tempdir)
+    }
+
+    fn names(ws: &Workspace, spec: Packages) -> CargoResult<Vec<String>> {
+        let members = spec.get_packages(ws)?;
+        Ok(members.iter().map(|p| p.name().to_string()).collect())
+    }
+
+    #[test]
+    fn test_members() -> CargoResult<()> {
+        let tempdir = workspace()?;
+        let config = cargo::Config::default()?;
+        let ws = Workspace::new(&tempdir.path().join("Cargo.toml"), &config)?;
+        let from_flags = |workspace, exclude: &[&str], package: &[&str]| {
+            let strings = |ss: &[&str]| ss.iter().map(|&s| s.to_owned()).collect();
+            Packages::from_flags(workspace, strings(exclude), strings(package))
+        };
+
+        assert_eq!(names(&ws, from_flags(true, &[], &[])?)?, ["a", "b", "c"]);
+        assert_eq!(names(&ws, from_flags(true, &["b"], &[])?)?, ["a", "c"]);
+        assert_eq!(
+            names(&ws, from_flags(false, &[], &["b", "c"])?)?,
+            ["b", "c"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile_options_for_targets() -> CargoResult<()> {
+        let tempdir = workspace()?;
+        let config = cargo::Config::default()?;
+        let manifest_path = tempdir.path().join("Cargo.toml");
+        let ws = Workspace::new(&manifest_path, &config)?;
+        let package = |name: &str| {
+            let path = Path::new(name).join("Cargo.toml");
+            ws.members()
+                .find(|p| p.manifest_path() == tempdir.path().join(&path))
+                .unwrap()
+        };
+        let (none, bin_x) = (None, Some("x".to_owned()));
+        let opts = |name, all_targets, lib, bin, triple| super::CompileOptionsForTargets {
+            ws: &ws,
+            package: package(name),
+            jobs: &None,
+            all_targets,
+            lib,
+            bin,
+            example: &None,
+            test: &None,
+            bench: &None,
+            triple,
+            release: false,
+            features: &[],
+            all_features: false,
+            no_default_features: false,
+            manifest_path: &manifest_path,
+            compile_mode: CompileMode::Check { test: false },
+        };
+        let targets = |opts: super::CompileOptionsForTargets| -> CargoResult<Vec<String>> {
+            let (_, targets) = opts.compile_options_for_targets()?;
+            Ok(targets.iter().map(|t| t.description_named()).collect())
+        };
+
+        let all = ["lib", r#"bin "x""#, r#"bin "y""#, r#"test "t""#];
+        assert_eq!(targets(opts("a", true, false, &none, None))?, all);
+        // `all_targets` takes precedence. The CLI rejects the combination.
+        assert_eq!(targets(opts("a", true, true, &bin_x, None))?, all);
+        assert_eq!(targets(opts("a", false, true, &none, None))?, ["lib"]);
+        assert_eq!(
+            targets(opts("a", false, false, &bin_x, None))?,
+            [r#"bin "x""#]
+        );
+        let err = targets(opts("a", false, false, &none, None)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Could not determine which binary to run"));
+        assert_eq!(targets(opts("b", false, false, &none, None))?, ["lib"]);
+        assert_eq!(
+            targets(opts("c", false, false, &none, None))?,
+            [r#"bin "c""#]
+        );
+
+        let (compile_opts, _) =
+            opts("c", false, false, &none, None).compile_options_for_targets()?;
+        assert_eq!(compile_opts.build_config.requested_kind, CompileKind::Host);
+        let triple = Some("wasm32-unknown-unknown");
+        let (compile_opts, _) =
+            opts("c", false, false, &none, triple).compile_options_for_targets()?;
+        assert_eq!(
+            compile_opts.build_config.requested_kind,
+            CompileKind::Target(CompileTarget::new("wasm32-unknown-unknown")?),
+        );
+        Ok(())
+    }
+}